@@ -1,5 +1,7 @@
 use macroquad::prelude::*;
 
+use crate::renderer::ViewBinding;
+
 //pub const GAME_SIZE_X: i32 = 160;
 pub const GAME_SIZE_X: i32 = 1024;
 pub const GAME_SIZE_Y: i32 = 768;
@@ -24,6 +26,154 @@ pub const KEY_FASTER: KeyCode = KeyCode::LeftShift;
 pub const KEY_EXIT: KeyCode = KeyCode::Escape;
 pub const KEY_CHANGE_VIEW: KeyCode = KeyCode::Tab;
 pub const KEY_CHANGE_VIEW_POINCARE: KeyCode = KeyCode::P;
+pub const KEY_CYCLE_VIEW: KeyCode = KeyCode::V;
+pub const KEY_MINIMAP: KeyCode = KeyCode::M;
+/// Brings the disk views back to the player after panning or zooming them.
+pub const KEY_RECENTRE: KeyCode = KeyCode::C;
+/// Zoom of the disk views is multiplied by this for each step of the wheel.
+pub const ZOOM_STEP: f32 = 1.15;
+pub const MIN_ZOOM: f32 = 0.5;
+pub const MAX_ZOOM: f32 = 50.;
+/// How the keys switch between the views while playing.
+pub const VIEW_BINDINGS: [ViewBinding; 3] = [
+    ViewBinding::Toggle(KEY_CHANGE_VIEW, "klein"),
+    ViewBinding::Toggle(KEY_CHANGE_VIEW_POINCARE, "poincare"),
+    ViewBinding::Cycle(KEY_CYCLE_VIEW),
+];
+pub const KEY_FLASHLIGHT: KeyCode = KeyCode::F;
+pub const KEY_SELECT: KeyCode = KeyCode::Enter;
+pub const KEY_MENU_UP: KeyCode = KeyCode::Up;
+pub const KEY_MENU_DOWN: KeyCode = KeyCode::Down;
+pub const KEY_QUICKSAVE: KeyCode = KeyCode::F5;
+pub const KEY_QUICKLOAD: KeyCode = KeyCode::F9;
+pub const KEY_EXPORT_KLEIN: KeyCode = KeyCode::F6;
+pub const KEY_EXPORT_POINCARE: KeyCode = KeyCode::F7;
+pub const KEY_EDIT_WALK: KeyCode = KeyCode::F2;
+pub const KEY_EDIT_WALLS: KeyCode = KeyCode::Key1;
+pub const KEY_EDIT_PICKUPS: KeyCode = KeyCode::Key2;
+pub const KEY_EDIT_TEXTURES: KeyCode = KeyCode::Key3;
+pub const KEY_EDIT_NEXT_TEXTURE: KeyCode = KeyCode::T;
+pub const KEY_EDIT_SNAP: KeyCode = KeyCode::G;
+pub const KEY_EDIT_DELETE: KeyCode = KeyCode::Delete;
+/// Undo, redo and save are pressed along with control.
+pub const KEY_EDIT_CONTROL: KeyCode = KeyCode::LeftControl;
+pub const KEY_EDIT_UNDO: KeyCode = KeyCode::Z;
+pub const KEY_EDIT_REDO: KeyCode = KeyCode::Y;
+pub const KEY_EDIT_SAVE: KeyCode = KeyCode::S;
 
 pub const OBJECT_RADIUS: f32 = 0.02;
 pub const OBJECT_COLOR: Color = WHITE;
+
+pub const GHOST_DIR: &str = "ghosts";
+pub const GHOST_SAMPLE_INTERVAL: f64 = 0.05;
+pub const GHOST_RADIUS: f32 = 0.015;
+pub const GHOST_COLOR: Color = Color {
+    r: 0.6,
+    g: 0.8,
+    b: 1.0,
+    a: 0.4,
+};
+
+/// Distance from the exit at which the player reaches it.
+pub const EXIT_RADIUS: f64 = 0.05;
+pub const EXIT_COLOR: Color = Color {
+    r: 0.2,
+    g: 1.0,
+    b: 0.4,
+    a: 0.6,
+};
+
+/// Tiles of the floor are drawn around the player up to this distance.
+pub const FLOOR_RADIUS: f64 = 4.;
+/// Edges of floor tiles are split into this many pieces, so they bend
+/// as the projection does.
+pub const FLOOR_SUBDIVISIONS: usize = 4;
+pub const FLOOR_COLORS: [Color; 2] = [
+    Color {
+        r: 0.45,
+        g: 0.45,
+        b: 0.5,
+        a: 1.0,
+    },
+    Color {
+        r: 0.3,
+        g: 0.3,
+        b: 0.35,
+        a: 1.0,
+    },
+];
+pub const CEILING_HEIGHT: f32 = WALL_HEIGHT;
+pub const CEILING_COLORS: [Color; 2] = [
+    Color {
+        r: 0.2,
+        g: 0.2,
+        b: 0.25,
+        a: 1.0,
+    },
+    Color {
+        r: 0.15,
+        g: 0.15,
+        b: 0.2,
+        a: 1.0,
+    },
+];
+
+/// Side of the minimap's texture, in pixels.
+pub const MINIMAP_RESOLUTION: u32 = 512;
+/// Distance from the minimap to the edges of the screen, in pixels.
+pub const MINIMAP_MARGIN: f32 = 10.;
+
+/// Fog hides this much more of what is one unit further away.
+pub const FOG_DENSITY: f64 = 0.35;
+pub const FOG_COLOR: Color = GRAY;
+/// Light reaching everything, on maps with lights and on those without.
+pub const AMBIENT_LIGHT: f32 = 0.25;
+pub const UNLIT_AMBIENT_LIGHT: f32 = 0.85;
+/// Lights are at full intensity up to this distance.
+pub const LIGHT_RADIUS: f64 = 0.1;
+pub const FLASHLIGHT_INTENSITY: f32 = 1.;
+/// Angle off the player's heading where the flashlight's beam ends.
+pub const FLASHLIGHT_ANGLE: f64 = 0.4;
+
+/// How far the player sees walls and pickups to put them on the automap.
+pub const EXPLORE_RADIUS: f64 = 1.5;
+/// A new explored region is kept once the player is this far from the others.
+pub const EXPLORE_SPACING: f64 = 0.1;
+/// Lines of sight cast around the player to outline an explored region.
+pub const EXPLORE_RAYS: usize = 48;
+/// Points along a wall checked to be in sight.
+pub const EXPLORE_WALL_SAMPLES: usize = 8;
+/// Explored regions further than this are not drawn, being at the edge of the disk.
+pub const EXPLORE_DRAW_DISTANCE: f64 = 5.;
+pub const EXPLORED_COLOR: Color = Color {
+    r: 0.12,
+    g: 0.12,
+    b: 0.2,
+    a: 1.0,
+};
+
+/// File next to a map listing the textures its walls can use.
+pub const TEXTURE_MANIFEST: &str = "textures.json";
+
+pub const SAVE_DIR: &str = "saves";
+pub const EXPORT_DIR: &str = "exports";
+pub const AUTOSAVE_SLOT: usize = 0;
+pub const QUICKSAVE_SLOT: usize = 1;
+
+/// Distance between neighbouring points of the editor's snapping grid.
+pub const EDITOR_GRID_STEP: f64 = 0.1;
+/// How close to a vertex or wall a click picks it.
+pub const EDITOR_PICK_DISTANCE: f64 = 0.03;
+/// Wall ends closer than this are dragged together.
+pub const EDITOR_JOIN_DISTANCE: f64 = 1e-3;
+pub const EDITOR_UNDO_LIMIT: usize = 100;
+pub const EDITOR_VERTEX_COLOR: Color = YELLOW;
+pub const EDITOR_GRID_COLOR: Color = Color {
+    r: 0.5,
+    g: 0.5,
+    b: 0.5,
+    a: 0.5,
+};
+
+pub const RELOAD_INTERVAL: f64 = 0.5;
+pub const RELOAD_MATCH_DISTANCE: f64 = 1e-3;
@@ -0,0 +1,368 @@
+use macroquad::prelude::*;
+use macroquad::ui::*;
+use nalgebra::Matrix3;
+
+use crate::constants::*;
+use crate::fpp_renderer::FppRenderer;
+use crate::poincare_renderer::PoincareRenderer;
+use crate::renderer::ViewSettings;
+use crate::game::editor::{grid_point, snap, Editor, Vertex};
+use crate::game::svgloader::Model;
+use crate::game::Game;
+use crate::state::{walk, Renderers};
+use crate::top_down_renderer::{disk_camera, TopDownRenderer};
+use crate::utils::hyperpoint::*;
+use crate::utils::kleinpoint::*;
+use crate::utils::poincarepoint::*;
+
+/// Points of the grid drawn in each direction from the origin.
+const GRID_EXTENT: i32 = 20;
+
+/// Clicks further than this from the center of the disk are ignored,
+/// as the points there are too far away to place precisely.
+const MAX_RADIUS: f64 = 0.98;
+
+/// What clicking on the map does.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tool {
+    /// Places the ends of walls, one after the other.
+    Walls,
+    Pickups,
+    /// Gives the wall clicked on the selected texture.
+    Textures,
+}
+
+/// Edits a map on the Klein or Poincaré minimap. The map can be walked
+/// in at any time to test it, and edits are made in world coordinates.
+pub struct EditMode {
+    editor: Editor,
+    /// Isometry from world coordinates to the view.
+    view: Matrix3<f64>,
+    model: Model,
+    tool: Tool,
+    textures: Vec<String>,
+    /// Index of the texture given to walls.
+    texture: usize,
+    snap: bool,
+    /// End of the last wall placed, where the next one begins.
+    pending: Option<HyperPoint>,
+    dragging: Option<Vertex>,
+    /// Game testing the map, while walking in it.
+    walking: Option<Game>,
+    /// How the last save went.
+    notice: Option<String>,
+    /// Set when leaving was asked for with unsaved changes,
+    /// so asking again leaves without saving.
+    leaving: bool,
+}
+
+impl EditMode {
+    /// Starts editing, looking from the start of the map.
+    pub fn new(editor: Editor) -> EditMode {
+        EditMode {
+            view: editor.file().info.start.isometry(),
+            textures: FppRenderer::texture_names_for(&editor.path),
+            editor,
+            model: Model::Klein,
+            tool: Tool::Walls,
+            texture: 0,
+            snap: false,
+            pending: None,
+            dragging: None,
+            walking: None,
+            notice: None,
+            leaving: false,
+        }
+    }
+
+    /// Draws the map with the vertices, and the help.
+    pub fn draw(&self, renderers: &Renderers) {
+        if let Some(game) = &self.walking {
+            renderers.handle_keys(game);
+            renderers.render(game);
+            game.display_hud();
+            root_ui().label(None, "F2: back to editing");
+            return;
+        }
+
+        let mut map = self.editor.to_map();
+        map.set_transform(&self.view);
+        let settings = ViewSettings::default();
+        match self.model {
+            Model::Klein => TopDownRenderer::new().render_map(&map, None, &settings),
+            Model::Poincare => PoincareRenderer::new().render_map(&map, None, &settings),
+        }
+
+        if self.snap {
+            for i in -GRID_EXTENT..=GRID_EXTENT {
+                for j in -GRID_EXTENT..=GRID_EXTENT {
+                    if let Some((x, y)) = self.to_disk(&grid_point(i, j)) {
+                        draw_circle(x, y, 0.002, EDITOR_GRID_COLOR);
+                    }
+                }
+            }
+        }
+        for vertex in self.editor.vertices() {
+            if let Some((x, y)) = self.to_disk(&self.editor.position(vertex)) {
+                draw_circle_lines(x, y, 0.008, 0.002, EDITOR_VERTEX_COLOR);
+            }
+        }
+        if let Some(cursor) = self.target() {
+            if let Some((x, y)) = self.to_disk(&cursor) {
+                draw_circle(x, y, 0.004, EDITOR_VERTEX_COLOR);
+                if let Some((px, py)) = self.pending.as_ref().and_then(|p| self.to_disk(p)) {
+                    draw_line(px, py, x, y, 0.003, EDITOR_VERTEX_COLOR);
+                }
+            }
+        }
+
+        set_default_camera();
+        let tool = match self.tool {
+            Tool::Walls => "walls",
+            Tool::Pickups => "pickups",
+            Tool::Textures => "textures",
+        };
+        root_ui().label(
+            None,
+            &format!(
+                "Editing {}: {}, texture {}{}",
+                self.editor.path,
+                tool,
+                self.textures[self.texture],
+                if self.snap { ", snapping" } else { "" }
+            ),
+        );
+        root_ui().label(
+            None,
+            "1-3: tool  T: texture  G: grid  P: model  Right drag: move  Del: delete",
+        );
+        root_ui().label(None, "Ctrl+Z/Y: undo/redo  Ctrl+S: save  F2: walk");
+        if self.leaving {
+            root_ui().label(None, "The map has unsaved changes, press Escape again to leave without saving");
+        } else if let Some(notice) = &self.notice {
+            root_ui().label(None, notice);
+        }
+    }
+
+    /// Handles input for one frame. Returns false once the editor is left.
+    /// With `fog_of_war`, what is seen while walking is explored.
+    pub fn update(&mut self, fog_of_war: bool) -> bool {
+        if is_key_pressed(KEY_EDIT_WALK) {
+            self.toggle_walking();
+        }
+        if let Some(game) = &mut self.walking {
+            game.fog_of_war = fog_of_war;
+            walk(game);
+            if is_key_pressed(KEY_EXIT) {
+                self.toggle_walking();
+            }
+            return true;
+        }
+        if is_key_pressed(KEY_EXIT) {
+            if self.pending.is_none() && self.dragging.is_none() {
+                if self.leaving || !self.editor.is_modified() {
+                    return false;
+                }
+                self.leaving = true;
+                return true;
+            }
+            self.pending = None;
+            self.dragging = None;
+        }
+        if get_last_key_pressed().is_some() {
+            self.leaving = false;
+        }
+
+        if is_key_down(KEY_EDIT_CONTROL) {
+            if is_key_pressed(KEY_EDIT_UNDO) {
+                self.editor.undo();
+                self.pending = None;
+            }
+            if is_key_pressed(KEY_EDIT_REDO) {
+                self.editor.redo();
+            }
+            if is_key_pressed(KEY_EDIT_SAVE) {
+                self.notice = Some(match self.editor.save() {
+                    Ok(path) => format!("Saved the map to {}", path.display()),
+                    Err(e) => format!("Could not save the map: {}", e),
+                });
+            }
+        } else {
+            self.pan();
+            self.handle_keys();
+        }
+        self.handle_mouse();
+        true
+    }
+
+    /// Starts walking in the map where the view is centered,
+    /// or goes back to editing where the walk ended.
+    fn toggle_walking(&mut self) {
+        match self.walking.take() {
+            Some(game) => self.view = *game.map.get_transform(),
+            None => {
+                // Walking tests the rule the map itself sets.
+                let mut game = Game::new(self.editor.to_map(), &self.editor.path, None);
+                game.map.set_transform(&self.view);
+                self.walking = Some(game);
+            }
+        }
+    }
+
+    /// Moves the view with the same keys as the player.
+    fn pan(&mut self) {
+        let movement = MOVEMENT_SPEED * get_frame_time() as f64;
+        let rotation = ROTATION_SPEED * get_frame_time() as f64;
+        let mut step = Matrix3::<f64>::identity();
+        if is_key_down(KEY_FORWARD) || is_key_down(KEY_FORWARD_ALT) {
+            step *= HyperPoint::translation_matrix(-movement, 0.);
+        }
+        if is_key_down(KEY_BACKWARD) || is_key_down(KEY_BACKWARD_ALT) {
+            step *= HyperPoint::translation_matrix(movement, 0.);
+        }
+        if is_key_down(KEY_STRAFE_L) {
+            step *= HyperPoint::translation_matrix(0., movement);
+        }
+        if is_key_down(KEY_STRAFE_R) {
+            step *= HyperPoint::translation_matrix(0., -movement);
+        }
+        if is_key_down(KEY_LEFT) {
+            step *= HyperPoint::rotation_matrix(-rotation);
+        }
+        if is_key_down(KEY_RIGHT) {
+            step *= HyperPoint::rotation_matrix(rotation);
+        }
+        self.view = step * self.view;
+    }
+
+    fn handle_keys(&mut self) {
+        for &(key, tool) in &[
+            (KEY_EDIT_WALLS, Tool::Walls),
+            (KEY_EDIT_PICKUPS, Tool::Pickups),
+            (KEY_EDIT_TEXTURES, Tool::Textures),
+        ] {
+            if is_key_pressed(key) {
+                self.tool = tool;
+                self.pending = None;
+            }
+        }
+        if is_key_pressed(KEY_EDIT_NEXT_TEXTURE) {
+            self.texture = (self.texture + 1) % self.textures.len();
+        }
+        if is_key_pressed(KEY_EDIT_SNAP) {
+            self.snap = !self.snap;
+        }
+        if is_key_pressed(KEY_CHANGE_VIEW_POINCARE) {
+            self.model = match self.model {
+                Model::Klein => Model::Poincare,
+                Model::Poincare => Model::Klein,
+            };
+        }
+        if is_key_pressed(KEY_EDIT_DELETE) {
+            if let Some(point) = self.cursor() {
+                if let Some(vertex) = self.editor.vertex_at(&point, EDITOR_PICK_DISTANCE) {
+                    self.editor.remove(vertex);
+                } else if let Some(wall) = self.editor.wall_at(&point, EDITOR_PICK_DISTANCE) {
+                    self.editor.remove_wall(wall);
+                }
+                self.pending = None;
+            }
+        }
+    }
+
+    fn handle_mouse(&mut self) {
+        if self.dragging.is_some() {
+            if !is_mouse_button_down(MouseButton::Right) {
+                self.dragging = None;
+            } else if let Some(point) = self.snapped_cursor() {
+                self.editor.drag(&point);
+            }
+            return;
+        }
+        if is_mouse_button_pressed(MouseButton::Right) {
+            self.pending = None;
+            if let Some(point) = self.cursor() {
+                self.dragging = self.editor.vertex_at(&point, EDITOR_PICK_DISTANCE);
+                if let Some(vertex) = self.dragging {
+                    self.editor.begin_drag(vertex);
+                }
+            }
+        }
+        if !is_mouse_button_pressed(MouseButton::Left) {
+            return;
+        }
+        let (point, target) = match (self.cursor(), self.target()) {
+            (Some(point), Some(target)) => (point, target),
+            _ => return,
+        };
+        let texture = &self.textures[self.texture];
+        match self.tool {
+            Tool::Walls => {
+                if let Some(beginning) = self.pending.take() {
+                    self.editor.add_wall(&beginning, &target, texture);
+                }
+                self.pending = Some(target);
+            }
+            Tool::Pickups => self.editor.add_pickup(&target),
+            Tool::Textures => {
+                if let Some(wall) = self.editor.wall_at(&point, EDITOR_PICK_DISTANCE) {
+                    self.editor.set_texture(wall, texture);
+                }
+            }
+        }
+    }
+
+    /// Point of the world under the mouse, if it is inside the disk.
+    fn cursor(&self) -> Option<HyperPoint> {
+        let (x, y) = mouse_position();
+        let mouse = disk_camera(&ViewSettings::default()).screen_to_world(vec2(x, y));
+        let (x, y) = (mouse.x as f64, mouse.y as f64);
+        if x * x + y * y >= MAX_RADIUS * MAX_RADIUS {
+            return None;
+        }
+        let mut point = match self.model {
+            Model::Klein => HyperPoint::from(KleinPoint::new(x, y)),
+            Model::Poincare => HyperPoint::from(PoincarePoint::new(x, y)),
+        };
+        point.transform(&HyperPoint::inverse_isometry(&self.view));
+        Some(point)
+    }
+
+    /// The cursor, on the grid when snapping.
+    fn snapped_cursor(&self) -> Option<HyperPoint> {
+        let point = self.cursor()?;
+        Some(if self.snap { snap(&point) } else { point })
+    }
+
+    /// Where a click places a point: on the vertex under the mouse,
+    /// so walls join, or else at the snapped cursor.
+    fn target(&self) -> Option<HyperPoint> {
+        let point = self.cursor()?;
+        match self.editor.vertex_at(&point, EDITOR_PICK_DISTANCE) {
+            Some(vertex) => Some(self.editor.position(vertex)),
+            None => self.snapped_cursor(),
+        }
+    }
+
+    /// Position on the screen's disk of a point in world coordinates,
+    /// if it is inside it.
+    fn to_disk(&self, point: &HyperPoint) -> Option<(f32, f32)> {
+        let mut point = *point;
+        point.transform(&self.view);
+        let (x, y) = match self.model {
+            Model::Klein => {
+                let point = KleinPoint::from(point);
+                (point.0.x, point.0.y)
+            }
+            Model::Poincare => {
+                let point = PoincarePoint::from(&point);
+                (point.0.x, point.0.y)
+            }
+        };
+        if x * x + y * y < 1. {
+            Some((x as f32, y as f32))
+        } else {
+            None
+        }
+    }
+}
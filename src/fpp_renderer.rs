@@ -1,183 +1,420 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::collections::HashMap;
 
-use futures::executor;
+use macroquad::miniquad::TextureWrap;
 use macroquad::prelude::*;
+use macroquad::window::get_internal_gl;
 
 use crate::constants::*;
-use crate::{game::Game, utils::euclideanpoint::*, utils::kleinpoint::*, utils::poincarepoint::*};
+use crate::game::texturepack::TextureManifest;
+use crate::game::tiling::{Tile, Tiling};
+use crate::lighting::Lighting;
+use crate::renderer::{Renderer, ViewSettings};
+use crate::utils::hyperpoint::{HyperObject, HyperPoint, HyperWall};
+use crate::utils::point::{Point, Wall};
+use crate::{game::Game, utils::euclideanpoint::*, utils::kleinpoint::*};
+
+/// Textures bundled with the game, by name, as used in the `class` of SVG walls.
+/// They are used unless the map's manifest replaces them.
+const TEXTURES: [(&str, &[u8]); 9] = [
+    ("WALL", include_bytes!("../assets/textures/wall.png")),
+    ("MARBLE", include_bytes!("../assets/textures/marble.png")),
+    ("CONCRETE", include_bytes!("../assets/textures/concrete.png")),
+    ("wall1", include_bytes!("../assets/textures/wall.png")),
+    ("wall2", include_bytes!("../assets/textures/wall2.png")),
+    ("wall3", include_bytes!("../assets/textures/wall3.png")),
+    ("wall4", include_bytes!("../assets/textures/wall4.png")),
+    ("wall5", include_bytes!("../assets/textures/wall5.png")),
+    ("wall6", include_bytes!("../assets/textures/wall6.png")),
+];
+
+/// Walls are split into pieces about this long, in hyperbolic units.
+const WALL_PIECE_LENGTH: f64 = 0.05;
+const MAX_WALL_PIECES: usize = 64;
+
+/// A texture, with how it is drawn on walls.
+#[derive(Clone, Copy)]
+struct WallTexture {
+    texture: Texture2D,
+    /// World units covered by one repeat of the texture.
+    scale: f32,
+    tint: Color,
+}
+
+impl WallTexture {
+    fn new(texture: Texture2D) -> WallTexture {
+        // Walls repeat their texture along their length.
+        let ctx = unsafe { get_internal_gl() }.quad_context;
+        texture.raw_miniquad_texture_handle().set_wrap(ctx, TextureWrap::Repeat);
+        WallTexture {
+            texture,
+            scale: WALL_HEIGHT,
+            tint: WHITE,
+        }
+    }
+}
 
 /// FPP renderer in hyperbolic space.
 /// Converts world from hyperboloid to Klein model,
 /// then uses polar coordinates using the Klein metric to
 /// map walls and objects to Euclidean space and render them.
 pub struct FppRenderer {
-    textures: HashMap<String, Texture2D>,
+    textures: HashMap<String, WallTexture>,
+    /// Textures from the manifest of the map being played, and its name.
+    pack: RefCell<(String, HashMap<String, WallTexture>)>,
+    /// Why textures of the pack could not be loaded, until taken.
+    pack_errors: RefCell<Vec<String>>,
+    /// Drawn on walls whose texture is in neither table.
+    missing: WallTexture,
+    floor: Option<Floor>,
+    ceiling: bool,
+}
+
+/// Something drawn in the first person view, in the view's coordinates.
+enum Drawable<'a> {
+    Wall(&'a HyperWall),
+    /// A pickup, drawn see-through once collected.
+    Object(&'a HyperObject),
+    Exit(HyperPoint),
+    Ghost(HyperPoint),
+}
+
+impl Drawable<'_> {
+    /// Distance from the player to the closest point.
+    fn distance(&self) -> f64 {
+        match self {
+            Drawable::Wall(wall) => wall.distance_to_closest_point(),
+            Drawable::Object(object) => object.position.distance_to_origin(),
+            Drawable::Exit(point) | Drawable::Ghost(point) => point.distance_to_origin(),
+        }
+    }
+}
+
+/// A tiling drawn on the floor, in world coordinates.
+struct Floor {
+    tiling: Tiling,
+    /// Vertices of the tile at the origin.
+    vertices: Vec<HyperPoint>,
+    /// Tiles around the tile at the origin, drawn around the tile
+    /// the player is on.
+    patch: Vec<Tile>,
+    /// Tile the player was last on.
+    anchor: RefCell<Tile>,
 }
 impl FppRenderer {
-    /// Initializes the renderer, loads textures.
-    pub fn new() -> FppRenderer {
+    /// Initializes the renderer, loads textures. The floor is plain
+    /// unless a tiling is given, and the ceiling is only drawn if asked for.
+    pub fn new(tiling: Option<Tiling>, ceiling: bool) -> FppRenderer {
         FppRenderer {
             textures: Self::load_textures(),
+            pack: RefCell::new((String::new(), HashMap::new())),
+            pack_errors: RefCell::new(vec![]),
+            missing: Self::missing_texture(),
+            floor: tiling.map(|tiling| Floor {
+                tiling,
+                vertices: tiling.vertices(),
+                patch: tiling.patch(&Tile::origin(), FLOOR_RADIUS),
+                anchor: RefCell::new(Tile::origin()),
+            }),
+            ceiling,
         }
     }
 
-    /// Load textures. They are included in the executable at compile time.
-    fn load_textures() -> HashMap<String, Texture2D> {
-        let mut textures = HashMap::new();
-        textures.insert(
-            "WALL".to_string(),
-            Texture2D::from_file_with_format(include_bytes!("../assets/textures/wall.png"), None)
-        );
-        textures.insert(
-            "MARBLE".to_string(),
-            Texture2D::from_file_with_format(include_bytes!("../assets/textures/marble.png"), None)
-        );
-        textures.insert(
-            "CONCRETE".to_string(),
-            Texture2D::from_file_with_format(include_bytes!("../assets/textures/concrete.png"), None)
-        );
-        textures.insert(
-            "wall1".to_string(),
-            Texture2D::from_file_with_format(include_bytes!("../assets/textures/wall.png"), None)
-        );
-        textures.insert(
-            "wall2".to_string(),
-            Texture2D::from_file_with_format(include_bytes!("../assets/textures/wall2.png"), None)
-        );
-        textures.insert(
-            "wall3".to_string(),
-            Texture2D::from_file_with_format(include_bytes!("../assets/textures/wall3.png"), None)
-        );
-        textures.insert(
-            "wall4".to_string(),
-            Texture2D::from_file_with_format(include_bytes!("../assets/textures/wall4.png"), None)
-        );
-        textures.insert(
-            "wall5".to_string(),
-            Texture2D::from_file_with_format(include_bytes!("../assets/textures/wall5.png"), None)
-        );
-        textures.insert(
-            "wall6".to_string(),
-            Texture2D::from_file_with_format(include_bytes!("../assets/textures/wall6.png"), None)
-        );
+    /// Names of the bundled textures.
+    pub fn texture_names() -> Vec<String> {
+        TEXTURES.iter().map(|(name, _)| name.to_string()).collect()
+    }
 
-        textures
+    /// Names of the textures walls of a map can use: the bundled ones,
+    /// and those of the manifest next to it.
+    pub fn texture_names_for(map_name: &str) -> Vec<String> {
+        let mut names = Self::texture_names();
+        if let Ok(Some(manifest)) = TextureManifest::for_map(map_name) {
+            names.extend(manifest.textures.keys().cloned());
+        }
+        names
     }
 
-    /// Renders one frame into the screen.
-    pub fn render(&self, game: &Game) {
-        clear_background(BLACK);
-        Self::draw_floor();
+    /// Load textures. They are included in the executable at compile time.
+    fn load_textures() -> HashMap<String, WallTexture> {
+        TEXTURES
+            .iter()
+            .map(|(name, bytes)| {
+                let texture = Texture2D::from_file_with_format(bytes, None);
+                (name.to_string(), WallTexture::new(texture))
+            })
+            .collect()
+    }
 
-        set_camera(&Camera3D {
-            position: vec3(0., 0., 0.05),
-            up: vec3(0., 0., 1.),
-            target: vec3(1., 0., 0.05),
-            ..Default::default()
-        });
+    /// Magenta and black checkerboard, which stands out.
+    fn missing_texture() -> WallTexture {
+        let size = 8;
+        let bytes: Vec<u8> = (0..size * size)
+            .flat_map(|i| {
+                if (i / size + i % size) % 2 == 0 {
+                    vec![255, 0, 255, 255]
+                } else {
+                    vec![0, 0, 0, 255]
+                }
+            })
+            .collect();
+        let texture = Texture2D::from_rgba8(size as u16, size as u16, &bytes);
+        texture.set_filter(FilterMode::Nearest);
+        WallTexture::new(texture)
+    }
 
-        let walls_euclidean = game.map.get_walls_iter().map(|wall| {
-            let wall = KleinWall::from(wall.clone());
-            (&wall).into()
-        });
+    /// Loads the textures of the manifest next to the map, unless they
+    /// already are. Textures which can't be read or decoded are skipped,
+    /// so walls fall back to the bundled or missing texture.
+    /// Returns why the manifest or its textures could not be loaded.
+    fn load_pack(&self, map_name: &str) -> Vec<String> {
+        let mut pack = self.pack.borrow_mut();
+        if pack.0 == map_name {
+            return vec![];
+        }
+        *pack = (map_name.to_string(), HashMap::new());
 
-        for wall in walls_euclidean {
-            self.draw_wall(&wall);
+        let manifest = match TextureManifest::for_map(map_name) {
+            Ok(Some(manifest)) => manifest,
+            Ok(None) => return vec![],
+            Err(e) => return vec![format!("Could not load the textures: {}", e)],
+        };
+        let mut errors = vec![];
+        for (name, entry) in &manifest.textures {
+            match manifest.load_image(entry) {
+                Ok(image) => {
+                    let texture =
+                        Texture2D::from_rgba8(image.width() as u16, image.height() as u16, image.as_raw());
+                    let [r, g, b, a] = entry.tint;
+                    let texture = WallTexture {
+                        scale: entry.scale as f32,
+                        tint: Color::from_rgba(r, g, b, a),
+                        ..WallTexture::new(texture)
+                    };
+                    pack.1.insert(name.clone(), texture);
+                }
+                Err(e) => errors.push(format!("Could not load the texture {}: {}", name, e)),
+            }
         }
+        errors
+    }
 
-        let objects_euclidean = game.map.get_objects_iter().map(|obj| {
-            let obj = KleinObject::from(obj.clone());
-            (&obj).into()
-        });
+    /// Texture of the given name: from the map's manifest, else a bundled
+    /// one, else the missing texture pattern.
+    fn texture(&self, name: &str) -> WallTexture {
+        let pack = self.pack.borrow();
+        pack.1
+            .get(name)
+            .or_else(|| self.textures.get(name))
+            .copied()
+            .unwrap_or(self.missing)
+    }
 
-        for obj in objects_euclidean {
-            self.draw_object(&obj);
+    /// Everything drawn in a frame besides the floor, in map order.
+    fn render_queue<'a>(&self, game: &'a Game) -> Vec<Drawable<'a>> {
+        let mut queue: Vec<_> = game.map.get_walls_iter().map(Drawable::Wall).collect();
+        queue.extend(game.map.get_objects_iter().map(Drawable::Object));
+        queue.extend(game.exit_position().map(Drawable::Exit));
+        queue.extend(game.ghost_position().map(Drawable::Ghost));
+        queue
+    }
+
+    /// Whether something is see-through, and needs to be drawn after
+    /// what is behind it.
+    fn is_translucent(&self, drawable: &Drawable) -> bool {
+        match drawable {
+            Drawable::Wall(wall) => self.texture(&wall.texture).tint.a < 1.,
+            Drawable::Object(object) => !object.active,
+            Drawable::Exit(_) => EXIT_COLOR.a < 1.,
+            Drawable::Ghost(_) => GHOST_COLOR.a < 1.,
         }
     }
 
-    /// Draws textured sphere.
-    fn draw_object(&self, object: &EuclideanObject) {
-        if object.active {
-            draw_sphere(
-                Vec3::new(object.position.x as f32, object.position.y as f32, 0.02),
-                OBJECT_RADIUS,
-                self.textures.get("MARBLE").unwrap().clone(),
-                OBJECT_COLOR,
-            );
+    /// Draws the exit as a glowing dome on the floor.
+    /// It gives off its own light, so it is only fogged.
+    fn draw_exit(&self, position: &HyperPoint, lighting: &Lighting) {
+        let color = lighting.fog_only(position, EXIT_COLOR);
+        let position = EuclideanPoint::from(&KleinPoint::from(position));
+        let (x, y) = (position.x as f32, position.y as f32);
+        draw_sphere(Vec3::new(x, y, 0.), 0.03, None, color);
+    }
+
+    /// Draws the best run's ghost as a translucent figure.
+    fn draw_ghost(&self, position: &HyperPoint, lighting: &Lighting) {
+        let color = lighting.shade(position, GHOST_COLOR);
+        let position = EuclideanPoint::from(&KleinPoint::from(position));
+        let (x, y) = (position.x as f32, position.y as f32);
+        draw_sphere(Vec3::new(x, y, GHOST_RADIUS), GHOST_RADIUS, None, color);
+        draw_sphere(
+            Vec3::new(x, y, GHOST_RADIUS * 2.5),
+            GHOST_RADIUS * 0.6,
+            None,
+            color,
+        );
+    }
+
+    /// Draws textured sphere, resting on the floor.
+    fn draw_object(&self, object: &HyperObject, lighting: &Lighting) {
+        let position = object.position;
+        let object = EuclideanObject::from(&KleinObject::from(object));
+        let color = if object.active {
+            OBJECT_COLOR
         } else {
-            draw_sphere(
-                Vec3::new(object.position.x as f32, object.position.y as f32, 0.02),
-                OBJECT_RADIUS,
-                self.textures.get("MARBLE").unwrap().clone(),
-                Color {
-                    r: 0.5,
-                    g: 0.5,
-                    b: 0.5,
-                    a: 0.5,
-                },
-            );
+            Color {
+                r: 0.5,
+                g: 0.5,
+                b: 0.5,
+                a: 0.5,
+            }
+        };
+        let color = lighting.shade(&position, color);
+        let size = object.size as f32;
+        let texture = self.texture("MARBLE").texture;
+        draw_sphere(
+            Vec3::new(object.position.x as f32, object.position.y as f32, size),
+            size,
+            texture,
+            color,
+        );
+    }
+
+    /// Draws textured wall. It is split into pieces along the geodesic,
+    /// so it bends as the projection does, and the texture repeats every
+    /// `scale` units of hyperbolic length and height. Vertices are lit
+    /// where they are.
+    fn draw_wall(&self, wall: &HyperWall, lighting: &Lighting) {
+        let texture = self.texture(&wall.texture);
+        let length = wall.beginning.distance_to(&wall.end);
+        let length = if length.is_finite() { length } else { 0. };
+        let pieces = ((length / WALL_PIECE_LENGTH).ceil() as usize).clamp(1, MAX_WALL_PIECES);
+        let height = wall.height as f32;
+        let v = height / texture.scale;
+
+        let mut vertices = vec![];
+        let mut indices = vec![];
+        for i in 0..=pieces {
+            let distance = length * i as f64 / pieces as f64;
+            let point = wall.beginning.towards(&wall.end, distance);
+            let color = lighting.shade(&point, texture.tint);
+            let point = EuclideanPoint::from(&point);
+            let (x, y) = (point.x as f32, point.y as f32);
+            let u = distance as f32 / texture.scale;
+            vertices.push(macroquad::models::Vertex {
+                position: Vec3::new(x, y, 0.),
+                uv: Vec2::new(u, 0.),
+                color,
+            });
+            vertices.push(macroquad::models::Vertex {
+                position: Vec3::new(x, y, height),
+                uv: Vec2::new(u, v),
+                color,
+            });
+            if i > 0 {
+                let k = 2 * i as u16;
+                indices.extend_from_slice(&[k - 2, k - 1, k, k - 1, k, k + 1]);
+            }
         }
+        draw_mesh(&Mesh {
+            vertices,
+            indices,
+            texture: Some(texture.texture),
+        });
     }
 
-    /// Draws textured wall.
-    fn draw_wall(&self, wall: &EuclideanWall) {
-        //println!("Drawing wall, beg:{},{}, end:{},{}", wall.beginning.x, wall.beginning.y, wall.end.x, wall.end.y);
-        let mesh = Mesh {
-            vertices: vec![
-                macroquad::models::Vertex {
-                    position: Vec3::new(wall.beginning.x as f32, wall.beginning.y as f32, 0.),
-                    uv: Vec2::new(0., 0.),
-                    color: WHITE,
-                },
-                macroquad::models::Vertex {
-                    position: Vec3::new(
-                        wall.beginning.x as f32,
-                        wall.beginning.y as f32,
-                        wall.height as f32,
-                    ),
-                    uv: Vec2::new(0., 1.),
-                    color: WHITE,
-                },
-                macroquad::models::Vertex {
-                    position: Vec3::new(wall.end.x as f32, wall.end.y as f32, 0.),
-                    uv: Vec2::new(1., 0.),
-                    color: WHITE,
-                },
-                macroquad::models::Vertex {
-                    position: Vec3::new(wall.end.x as f32, wall.end.y as f32, wall.height as f32),
-                    uv: Vec2::new(1., 1.),
-                    color: WHITE,
-                },
-            ],
-            indices: vec![0, 1, 2, 1, 2, 3],
-            texture: Some(self.textures.get(&wall.texture).unwrap().clone()),
-        };
-        draw_mesh(&mesh);
+    /// Draws the tiles around the player at the given height. Each tile is
+    /// a fan of sectors from its center, split along geodesics, darkening
+    /// towards the edges so tiles of the same color can be told apart.
+    fn draw_tiling(
+        &self,
+        floor: &Floor,
+        game: &Game,
+        lighting: &Lighting,
+        height: f32,
+        colors: &[Color; 2],
+    ) {
+        let transform = game.map.get_transform();
+        let mut player = HyperPoint::new(0., 0.);
+        player.transform(&HyperPoint::inverse_isometry(transform));
+        let anchor = floor.tiling.tile_at(&floor.anchor.borrow(), &player);
+        let two_colorable = floor.tiling.two_colorable();
+
+        let n = FLOOR_SUBDIVISIONS;
+        for tile in &floor.patch {
+            let isometry = transform * anchor.isometry * tile.isometry;
+            let moved = |point: &HyperPoint| {
+                let mut point = *point;
+                point.transform(&isometry);
+                point
+            };
+            let center = moved(&HyperPoint::new(0., 0.));
+            let parity = two_colorable && (anchor.parity != tile.parity);
+            let color = colors[parity as usize];
+
+            let mut vertices = vec![];
+            let mut indices = vec![];
+            for (k, a) in floor.vertices.iter().enumerate() {
+                let (a, b) = (moved(a), moved(&floor.vertices[(k + 1) % floor.vertices.len()]));
+                let edge = a.distance_to(&b);
+                let first = vertices.len() as u16;
+                for i in 0..=n {
+                    let point = a.towards(&b, edge * i as f64 / n as f64);
+                    let radius = center.distance_to(&point);
+                    for l in 0..=n {
+                        let t = l as f32 / n as f32;
+                        let point = center.towards(&point, radius * t as f64);
+                        let shade = 1. - 0.3 * t * t;
+                        let color = Color::new(color.r * shade, color.g * shade, color.b * shade, color.a);
+                        let color = lighting.shade(&point, color);
+                        let point = EuclideanPoint::from(&point);
+                        vertices.push(macroquad::models::Vertex {
+                            position: Vec3::new(point.x as f32, point.y as f32, height),
+                            uv: Vec2::new(0., 0.),
+                            color,
+                        });
+                    }
+                }
+                let row = (n + 1) as u16;
+                for i in 0..n as u16 {
+                    for l in 0..n as u16 {
+                        let v = first + i * row + l;
+                        indices.extend_from_slice(&[v, v + 1, v + row, v + 1, v + row, v + row + 1]);
+                    }
+                }
+            }
+            draw_mesh(&Mesh {
+                vertices,
+                indices,
+                texture: None,
+            });
+        }
+        *floor.anchor.borrow_mut() = anchor;
     }
 
-    /// Draws floor as a large gray flat surface.
+    /// Draws floor as a large flat surface, the color of the fog
+    /// everything fades into.
     fn draw_floor() {
         let mesh = Mesh {
             vertices: vec![
                 macroquad::models::Vertex {
                     position: Vec3::new(-1000., -1000., 0.),
                     uv: Vec2::new(0., 0.),
-                    color: GRAY,
+                    color: FOG_COLOR,
                 },
                 macroquad::models::Vertex {
                     position: Vec3::new(-1000., 1000., 0.),
                     uv: Vec2::new(0., 0.),
-                    color: GRAY,
+                    color: FOG_COLOR,
                 },
                 macroquad::models::Vertex {
                     position: Vec3::new(1000., -1000., 0.),
                     uv: Vec2::new(0., 0.),
-                    color: GRAY,
+                    color: FOG_COLOR,
                 },
                 macroquad::models::Vertex {
                     position: Vec3::new(1000., 1000., 0.),
                     uv: Vec2::new(0., 0.),
-                    color: GRAY,
+                    color: FOG_COLOR,
                 },
             ],
             indices: vec![0, 1, 2, 1, 2, 3],
@@ -186,3 +423,95 @@ impl FppRenderer {
         draw_mesh(&mesh);
     }
 }
+
+impl Renderer for FppRenderer {
+    /// Renders one frame into the screen.
+    fn render(&self, game: &Game, _settings: &ViewSettings) {
+        let errors = self.load_pack(&game.map_name);
+        self.pack_errors.borrow_mut().extend(errors);
+        let lighting = Lighting::new(game);
+        clear_background(BLACK);
+        Self::draw_floor();
+
+        set_camera(&Camera3D {
+            position: vec3(0., 0., 0.05),
+            up: vec3(0., 0., 1.),
+            target: vec3(1., 0., 0.05),
+            ..Default::default()
+        });
+
+        if let Some(floor) = &self.floor {
+            self.draw_tiling(floor, game, &lighting, 0., &FLOOR_COLORS);
+            if self.ceiling {
+                self.draw_tiling(floor, game, &lighting, CEILING_HEIGHT, &CEILING_COLORS);
+            }
+        }
+
+        let (opaque, mut translucent): (Vec<_>, Vec<_>) = self
+            .render_queue(game)
+            .into_iter()
+            .partition(|drawable| !self.is_translucent(drawable));
+        sort_back_to_front(&mut translucent);
+        for drawable in opaque.iter().chain(&translucent) {
+            match drawable {
+                Drawable::Wall(wall) => self.draw_wall(wall, &lighting),
+                Drawable::Object(object) => self.draw_object(object, &lighting),
+                Drawable::Exit(exit) => self.draw_exit(exit, &lighting),
+                Drawable::Ghost(ghost) => self.draw_ghost(ghost, &lighting),
+            }
+        }
+    }
+
+    fn take_errors(&self) -> Vec<String> {
+        self.pack_errors.take()
+    }
+}
+
+/// Sorts drawables from the farthest to the closest. Translucent things
+/// only blend with what is behind them if that was drawn first.
+fn sort_back_to_front(drawables: &mut [Drawable]) {
+    drawables.sort_by(|a, b| {
+        b.distance()
+            .partial_cmp(&a.distance())
+            .unwrap_or(Ordering::Equal)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sort_back_to_front() {
+        let wall = HyperWall {
+            beginning: HyperPoint::new(0.5, -1.),
+            end: HyperPoint::new(0.5, 1.),
+            texture: String::new(),
+            height: WALL_HEIGHT as f64,
+        };
+        let object = HyperObject {
+            position: HyperPoint::new(-0.8, 0.),
+            active: false,
+            size: 0.02,
+        };
+        let mut drawables = vec![
+            Drawable::Ghost(HyperPoint::new(0.1, 0.)),
+            Drawable::Wall(&wall),
+            Drawable::Exit(HyperPoint::new(0., 2.)),
+            Drawable::Object(&object),
+        ];
+        sort_back_to_front(&mut drawables);
+        let order: Vec<&str> = drawables
+            .iter()
+            .map(|drawable| match drawable {
+                Drawable::Wall(_) => "wall",
+                Drawable::Object(_) => "object",
+                Drawable::Exit(_) => "exit",
+                Drawable::Ghost(_) => "ghost",
+            })
+            .collect();
+        // The wall is farther along its ends than the object, but its
+        // middle is closer.
+        assert_eq!(order, vec!["exit", "object", "wall", "ghost"]);
+    }
+}
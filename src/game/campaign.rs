@@ -0,0 +1,204 @@
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+use crate::constants::*;
+
+/// What the player has to do to complete a level.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompletionRule {
+    /// Collect every object on the map.
+    #[default]
+    CollectAll,
+    /// Collect at least the given number of objects.
+    Collect(usize),
+    /// Collect every object within the given number of seconds.
+    /// The run fails once the time is up.
+    CollectAllWithin(f64),
+    /// Reach the exit of the map.
+    ReachExit,
+}
+
+impl CompletionRule {
+    /// Whether the rule is satisfied.
+    ///
+    /// # Parameters:
+    ///		- `collected`:	Number of objects collected so far.
+    ///		- `total`:		Number of objects on the map.
+    ///		- `elapsed`:	Seconds since the start of the run.
+    ///		- `at_exit`:	Whether the player stands at the exit.
+    pub fn is_met(&self, collected: usize, total: usize, elapsed: f64, at_exit: bool) -> bool {
+        match self {
+            CompletionRule::CollectAll => collected == total,
+            CompletionRule::Collect(n) => collected >= *n,
+            CompletionRule::CollectAllWithin(limit) => collected == total && elapsed <= *limit,
+            CompletionRule::ReachExit => at_exit,
+        }
+    }
+
+    /// Whether the rule can no longer be met, `elapsed` seconds into a run.
+    pub fn is_failed(&self, elapsed: f64) -> bool {
+        match self {
+            CompletionRule::CollectAllWithin(limit) => elapsed > *limit,
+            _ => false,
+        }
+    }
+
+    /// Reads a rule written as in SVG maps: `collect-all`, `collect N`,
+    /// `collect-all-within SECONDS` or `reach-exit`.
+    pub fn parse(value: &str) -> Option<CompletionRule> {
+        let words: Vec<&str> = value.split_whitespace().collect();
+        match words.as_slice() {
+            ["collect-all"] => Some(CompletionRule::CollectAll),
+            ["collect", n] => n.parse().ok().map(CompletionRule::Collect),
+            ["collect-all-within", limit] => limit
+                .parse()
+                .ok()
+                .filter(|limit: &f64| limit.is_finite())
+                .map(CompletionRule::CollectAllWithin),
+            ["reach-exit"] => Some(CompletionRule::ReachExit),
+            _ => None,
+        }
+    }
+}
+
+/// Writes the rule as `CompletionRule::parse` reads it.
+impl fmt::Display for CompletionRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompletionRule::CollectAll => write!(f, "collect-all"),
+            CompletionRule::Collect(n) => write!(f, "collect {}", n),
+            CompletionRule::CollectAllWithin(limit) => write!(f, "collect-all-within {}", limit),
+            CompletionRule::ReachExit => write!(f, "reach-exit"),
+        }
+    }
+}
+
+/// A level of the campaign.
+#[derive(Clone, Deserialize)]
+pub struct Level {
+    /// File name of the level's map.
+    pub map: String,
+    pub title: String,
+    /// Overrides the rule the map sets, if any.
+    #[serde(default)]
+    pub rule: Option<CompletionRule>,
+}
+
+/// Ordered list of levels, read from a manifest.
+#[derive(Deserialize)]
+pub struct Campaign {
+    pub levels: Vec<Level>,
+}
+
+impl Campaign {
+    /// The campaign bundled into the executable, parsed on first use.
+    pub fn builtin() -> &'static Campaign {
+        static CAMPAIGN: OnceLock<Campaign> = OnceLock::new();
+        CAMPAIGN.get_or_init(|| serde_json::from_str(include_str!("../../assets/campaign.json")).unwrap())
+    }
+
+    /// Index of the level played on the given map.
+    pub fn find(&self, map: &str) -> Option<usize> {
+        self.levels.iter().position(|l| l.map == map)
+    }
+
+    /// The level following the one played on the given map.
+    pub fn next(&self, map: &str) -> Option<&Level> {
+        self.levels.get(self.find(map)? + 1)
+    }
+
+    /// Completion rule the campaign sets for the given map, if any.
+    pub fn rule_for(&self, map: &str) -> Option<CompletionRule> {
+        self.levels[self.find(map)?].rule.clone()
+    }
+}
+
+/// Levels completed so far, persisted between runs.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Progress {
+    /// Map file names of the completed levels.
+    pub completed: Vec<String>,
+}
+
+impl Progress {
+    /// Loads the progress, starting afresh if there is none.
+    pub fn load() -> Progress {
+        fs::read_to_string(Self::path())
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        fs::create_dir_all(SAVE_DIR).map_err(|e| e.to_string())?;
+        let content = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        fs::write(Self::path(), content).map_err(|e| e.to_string())
+    }
+
+    /// Marks the level played on the given map as completed.
+    pub fn complete(&mut self, map: &str) {
+        if !self.is_completed(map) {
+            self.completed.push(map.to_string());
+        }
+    }
+
+    pub fn is_completed(&self, map: &str) -> bool {
+        self.completed.iter().any(|m| m == map)
+    }
+
+    /// The first level is always unlocked, every other one
+    /// once the level before it is completed.
+    pub fn is_unlocked(&self, campaign: &Campaign, index: usize) -> bool {
+        index == 0
+            || campaign
+                .levels
+                .get(index - 1)
+                .is_some_and(|previous| self.is_completed(&previous.map))
+    }
+
+    fn path() -> PathBuf {
+        PathBuf::from(SAVE_DIR).join("progress.json")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_unlocking() {
+        let campaign = Campaign::builtin();
+        let mut progress = Progress::default();
+        assert!(progress.is_unlocked(campaign, 0));
+        assert!(!progress.is_unlocked(campaign, 1));
+
+        progress.complete(&campaign.levels[0].map);
+        assert!(progress.is_unlocked(campaign, 1));
+        assert!(!progress.is_unlocked(campaign, 2));
+    }
+
+    #[test]
+    fn test_parse_rule() {
+        assert_eq!(CompletionRule::parse("collect 3"), Some(CompletionRule::Collect(3)));
+        assert_eq!(
+            CompletionRule::parse(" collect-all-within  60 "),
+            Some(CompletionRule::CollectAllWithin(60.))
+        );
+        assert_eq!(CompletionRule::parse("reach-exit"), Some(CompletionRule::ReachExit));
+        assert_eq!(CompletionRule::parse("collect many"), None);
+        assert_eq!(CompletionRule::parse("win"), None);
+        let rule = CompletionRule::CollectAllWithin(12.5);
+        assert_eq!(CompletionRule::parse(&rule.to_string()), Some(rule));
+    }
+
+    #[test]
+    fn test_builtin_maps_exist() {
+        for level in &Campaign::builtin().levels {
+            assert!(crate::game::maps::load_builtin(&level.map).is_some());
+        }
+    }
+}
@@ -0,0 +1,315 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::hypermap::HyperMap;
+use super::maperror::MapLoadError;
+use super::mapfile::{MapFile, MapFormat};
+use super::maps;
+use super::validator::distance_to_segment;
+use crate::constants::*;
+use crate::utils::hyperpoint::*;
+use crate::utils::kleinpoint::*;
+use crate::utils::point::Point;
+use crate::utils::poincarepoint::*;
+
+/// A point of the map which can be dragged.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Vertex {
+    /// Beginning of the wall with the given index.
+    Beginning(usize),
+    End(usize),
+    Pickup(usize),
+}
+
+/// A map being edited, in world coordinates, with its history.
+pub struct Editor {
+    file: MapFile,
+    /// Where the map is saved.
+    pub path: String,
+    undo: Vec<MapFile>,
+    redo: Vec<MapFile>,
+    /// Whether the map changed since it was opened or last saved.
+    modified: bool,
+    /// Vertices moved by the drag going on.
+    dragged: Vec<Vertex>,
+}
+
+impl Editor {
+    /// Opens a map for editing: a bundled one, one from disk,
+    /// or a new empty one if there is no file at `path` yet.
+    pub fn open(path: &str) -> Result<Editor, MapLoadError> {
+        let file = if maps::is_builtin(path) || Path::new(path).exists() {
+            MapFile::from_map(&maps::load(path)?)
+        } else {
+            MapFile::new(vec![], vec![])
+        };
+        Ok(Editor::new(file, path))
+    }
+
+    pub fn new(file: MapFile, path: &str) -> Editor {
+        Editor {
+            file,
+            path: path.to_string(),
+            undo: vec![],
+            redo: vec![],
+            modified: false,
+            dragged: vec![],
+        }
+    }
+
+    pub fn file(&self) -> &MapFile {
+        &self.file
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    /// The edited map, in world coordinates.
+    pub fn to_map(&self) -> HyperMap {
+        self.file.to_map()
+    }
+
+    /// Returns the map to change, remembering how it was for `undo`.
+    fn edit(&mut self) -> &mut MapFile {
+        self.undo.push(self.file.clone());
+        if self.undo.len() > EDITOR_UNDO_LIMIT {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+        self.modified = true;
+        &mut self.file
+    }
+
+    /// Reverts the last edit. Returns false if there is none.
+    pub fn undo(&mut self) -> bool {
+        match self.undo.pop() {
+            Some(file) => {
+                self.redo.push(std::mem::replace(&mut self.file, file));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Redoes the last undone edit. Returns false if there is none.
+    pub fn redo(&mut self) -> bool {
+        match self.redo.pop() {
+            Some(file) => {
+                self.undo.push(std::mem::replace(&mut self.file, file));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn add_wall(&mut self, beginning: &HyperPoint, end: &HyperPoint, texture: &str) {
+        self.edit().walls.push(PoincareWall {
+            beginning: PoincarePoint::from(beginning),
+            end: PoincarePoint::from(end),
+            texture: texture.to_string(),
+            height: WALL_HEIGHT as f64,
+        });
+    }
+
+    pub fn add_pickup(&mut self, position: &HyperPoint) {
+        self.edit().objects.push(PoincareObject {
+            position: PoincarePoint::from(position),
+            active: true,
+            size: default_object_size(),
+        });
+    }
+
+    pub fn set_texture(&mut self, wall: usize, texture: &str) {
+        self.edit().walls[wall].texture = texture.to_string();
+    }
+
+    /// Removes the wall or pickup the vertex belongs to.
+    pub fn remove(&mut self, vertex: Vertex) {
+        match vertex {
+            Vertex::Beginning(wall) | Vertex::End(wall) => self.remove_wall(wall),
+            Vertex::Pickup(object) => {
+                self.edit().objects.remove(object);
+            }
+        }
+    }
+
+    pub fn remove_wall(&mut self, wall: usize) {
+        self.edit().walls.remove(wall);
+    }
+
+    /// Position of a vertex, in world coordinates.
+    pub fn position(&self, vertex: Vertex) -> HyperPoint {
+        let point = match vertex {
+            Vertex::Beginning(wall) => &self.file.walls[wall].beginning,
+            Vertex::End(wall) => &self.file.walls[wall].end,
+            Vertex::Pickup(object) => &self.file.objects[object].position,
+        };
+        HyperPoint::from(point.clone())
+    }
+
+    /// All vertices: both ends of every wall, and the pickups.
+    pub fn vertices(&self) -> Vec<Vertex> {
+        let walls = (0..self.file.walls.len()).flat_map(|i| vec![Vertex::Beginning(i), Vertex::End(i)]);
+        let pickups = (0..self.file.objects.len()).map(Vertex::Pickup);
+        walls.chain(pickups).collect()
+    }
+
+    /// The vertex closest to a point, if it is within `distance` of it.
+    pub fn vertex_at(&self, point: &HyperPoint, distance: f64) -> Option<Vertex> {
+        self.vertices()
+            .into_iter()
+            .map(|vertex| (vertex, self.position(vertex).distance_to(point)))
+            .filter(|(_, d)| *d < distance)
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .map(|(vertex, _)| vertex)
+    }
+
+    /// The wall closest to a point, if it is within `distance` of it.
+    pub fn wall_at(&self, point: &HyperPoint, distance: f64) -> Option<usize> {
+        // Measured with the point at the center, where the Klein disk
+        // is the least distorted.
+        let centering = point.centering_isometry();
+        let centered = |point: &PoincarePoint| {
+            let mut point = HyperPoint::from(point.clone());
+            point.transform(&centering);
+            let point = KleinPoint::from(point);
+            (point.0.x, point.0.y)
+        };
+        self.file
+            .walls
+            .iter()
+            .map(|wall| distance_to_segment((0., 0.), centered(&wall.beginning), centered(&wall.end)))
+            .enumerate()
+            .filter(|(_, d)| *d < distance)
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .map(|(wall, _)| wall)
+    }
+
+    /// Starts dragging a vertex, along with the ends of walls joined to it.
+    /// The map is remembered as it is, so the whole drag is undone at once.
+    pub fn begin_drag(&mut self, vertex: Vertex) {
+        self.edit();
+        let from = self.position(vertex);
+        self.dragged = match vertex {
+            Vertex::Pickup(_) => vec![vertex],
+            _ => self
+                .vertices()
+                .into_iter()
+                .filter(|v| !matches!(v, Vertex::Pickup(_)))
+                .filter(|&v| self.position(v).distance_to(&from) < EDITOR_JOIN_DISTANCE)
+                .collect(),
+        };
+    }
+
+    /// Moves the vertices being dragged.
+    pub fn drag(&mut self, to: &HyperPoint) {
+        let to = PoincarePoint::from(to);
+        for vertex in &self.dragged {
+            let point = match *vertex {
+                Vertex::Beginning(wall) => &mut self.file.walls[wall].beginning,
+                Vertex::End(wall) => &mut self.file.walls[wall].end,
+                Vertex::Pickup(object) => &mut self.file.objects[object].position,
+            };
+            *point = to.clone();
+        }
+    }
+
+    /// Writes the map to its path, in the format implied by the extension.
+    /// Bundled maps are written to `EXPORT_DIR` instead. Returns the path written.
+    pub fn save(&mut self) -> Result<PathBuf, String> {
+        let path = if maps::is_builtin(&self.path) {
+            fs::create_dir_all(EXPORT_DIR).map_err(|e| e.to_string())?;
+            PathBuf::from(EXPORT_DIR).join(&self.path)
+        } else {
+            PathBuf::from(&self.path)
+        };
+        let format = MapFormat::from_path(&self.path).unwrap_or(MapFormat::Json);
+        fs::write(&path, self.file.write(format)).map_err(|e| format!("{}: {}", path.display(), e))?;
+        self.modified = false;
+        Ok(path)
+    }
+}
+
+/// Point of the snapping grid, reached from the origin by going `i` steps
+/// along the x axis, then `j` steps along the geodesic perpendicular to it.
+/// The grid lines of constant `i` are geodesics.
+pub fn grid_point(i: i32, j: i32) -> HyperPoint {
+    let (u, v) = (i as f64 * EDITOR_GRID_STEP, j as f64 * EDITOR_GRID_STEP);
+    HyperPoint::new_with_z(u.sinh() * v.cosh(), v.sinh(), u.cosh() * v.cosh())
+}
+
+/// The grid point closest to a point, in grid coordinates.
+pub fn grid_coordinates(point: &HyperPoint) -> (i32, i32) {
+    let u = (point.0.x / point.0.z).atanh();
+    let v = point.0.y.asinh();
+    (
+        (u / EDITOR_GRID_STEP).round() as i32,
+        (v / EDITOR_GRID_STEP).round() as i32,
+    )
+}
+
+/// Moves a point to the closest grid point.
+pub fn snap(point: &HyperPoint) -> HyperPoint {
+    let (i, j) = grid_coordinates(point);
+    grid_point(i, j)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn same(a: &HyperPoint, b: &HyperPoint) -> bool {
+        (a.0 - b.0).norm() < 1e-9
+    }
+
+    #[test]
+    fn test_edit_and_undo() {
+        let mut editor = Editor::new(MapFile::new(vec![], vec![]), "new.json");
+        let (a, b) = (HyperPoint::new(0.1, 0.), HyperPoint::new(0.1, 0.3));
+        editor.add_wall(&a, &b, "WALL");
+        editor.add_wall(&b, &HyperPoint::new(-0.2, 0.3), "WALL");
+        editor.add_pickup(&HyperPoint::new(-0.1, -0.1));
+        assert_eq!(editor.vertices().len(), 5);
+        assert_eq!(editor.vertex_at(&HyperPoint::new(0.11, 0.3), 0.05), Some(Vertex::End(0)));
+        assert_eq!(editor.wall_at(&HyperPoint::new(0.12, 0.15), 0.05), Some(0));
+
+        // Dragging a corner moves the ends of both walls.
+        let c = HyperPoint::new(0.2, 0.4);
+        editor.begin_drag(Vertex::End(0));
+        editor.drag(&HyperPoint::new(0.15, 0.35));
+        editor.drag(&c);
+        assert!(same(&editor.position(Vertex::End(0)), &c));
+        assert!(same(&editor.position(Vertex::Beginning(1)), &c));
+
+        // Passing by another vertex does not pick it up.
+        editor.begin_drag(Vertex::Pickup(0));
+        editor.drag(&c);
+        editor.drag(&HyperPoint::new(-0.1, -0.1));
+        assert!(same(&editor.position(Vertex::End(0)), &c));
+        assert!(editor.undo());
+
+        editor.set_texture(1, "MARBLE");
+        assert!(editor.undo());
+        assert_eq!(editor.file().walls[1].texture, "WALL");
+        assert!(editor.undo());
+        assert!(same(&editor.position(Vertex::End(0)), &b));
+        assert!(editor.redo());
+        assert!(same(&editor.position(Vertex::Beginning(1)), &c));
+
+        editor.remove(Vertex::Pickup(0));
+        assert!(!editor.redo());
+        assert_eq!(editor.file().objects.len(), 0);
+    }
+
+    #[test]
+    fn test_snap() {
+        let point = grid_point(3, -2);
+        assert!(((-HyperPoint::minkowski_dot(&point, &point)) - 1.).abs() < 1e-9);
+        assert_eq!(grid_coordinates(&point), (3, -2));
+
+        let mut near = point;
+        near.transform(&HyperPoint::translation_matrix(0.01, 0.02));
+        assert!(same(&snap(&near), &point));
+    }
+}
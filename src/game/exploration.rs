@@ -0,0 +1,297 @@
+use std::f64::consts::PI;
+
+use nalgebra::Matrix3;
+use serde::{Deserialize, Serialize};
+
+use crate::constants::*;
+use crate::game::hypermap::HyperMap;
+use crate::utils::hyperpoint::{HyperPoint, HyperWall};
+use crate::utils::kleinpoint::KleinPoint;
+use crate::utils::point::Point;
+
+/// Ends of a wall on the Klein disk, where lines of sight are straight.
+type Segment = ([f64; 2], [f64; 2]);
+
+/// A part of the map the player has seen: the fan of points around
+/// where it was seen from, as far as the walls let the player see.
+#[derive(Clone, Serialize, Deserialize)]
+struct Region {
+    /// Wall whose frame the points are in, or none for the world's frame.
+    anchor: Option<usize>,
+    /// The center of the fan, then its outline.
+    fan: Vec<HyperPoint>,
+}
+
+/// What the player has seen of a map, for the automap.
+///
+/// Regions are fixed to the world, in the frame of the wall nearest to
+/// where they were seen from. Walls are moved into the view step by step
+/// as the player walks, so they stay accurate however far the player
+/// goes, which a frame at the world's origin would not: moving a point
+/// from it then cancels out numbers which grow exponentially.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Exploration {
+    /// Whether each wall of the map has been in the player's line of sight.
+    walls: Vec<bool>,
+    objects: Vec<bool>,
+    exit: bool,
+    regions: Vec<Region>,
+}
+
+impl Exploration {
+    pub fn new() -> Exploration {
+        Exploration::default()
+    }
+
+    /// Marks what can be seen from the origin of the map's view,
+    /// and the region around it if it is not explored yet.
+    pub fn look(&mut self, map: &HyperMap, exit: Option<&HyperPoint>) {
+        let walls: Vec<Segment> = map
+            .get_walls_iter()
+            .map(|wall| (klein(&wall.beginning), klein(&wall.end)))
+            .collect();
+        let sight = Sight { walls: &walls };
+
+        self.walls.resize(walls.len(), false);
+        self.objects.resize(map.get_objects_iter().count(), false);
+        for (i, (a, b)) in walls.iter().enumerate() {
+            self.walls[i] = self.walls[i]
+                || (0..=EXPLORE_WALL_SAMPLES).any(|k| {
+                    let t = k as f64 / EXPLORE_WALL_SAMPLES as f64;
+                    let point = [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t];
+                    sight.sees(point, Some(i))
+                });
+        }
+        for (seen, object) in self.objects.iter_mut().zip(map.get_objects_iter()) {
+            *seen = *seen || sight.sees(klein(&object.position), None);
+        }
+        if let Some(exit) = exit {
+            self.exit = self.exit || sight.sees(klein(exit), None);
+        }
+
+        let frames = Frames::new(map);
+        let explored = self.regions.iter().any(|region| {
+            frames.in_view(region.anchor, &region.fan[0]).distance_to_origin() < EXPLORE_SPACING
+        });
+        if explored {
+            return;
+        }
+        let anchor = map
+            .get_walls_iter()
+            .map(|wall| wall.beginning.distance_to_origin())
+            .enumerate()
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .map(|(i, _)| i);
+        let to_anchor = HyperPoint::inverse_isometry(&frames.to_view(anchor));
+        let fan = std::iter::once([0., 0.])
+            .chain((0..EXPLORE_RAYS).map(|k| sight.ray(2. * PI * k as f64 / EXPLORE_RAYS as f64)))
+            .map(|[x, y]| {
+                let mut point = HyperPoint::from(KleinPoint::new(x, y));
+                point.transform(&to_anchor);
+                point
+            })
+            .collect();
+        self.regions.push(Region { anchor, fan });
+    }
+
+    /// Whether the exploration can be used on the map. Regions are
+    /// anchored to walls by their index, so it is made for one number
+    /// of walls, unless nothing has been looked at yet.
+    pub fn fits(&self, map: &HyperMap) -> bool {
+        self.walls.is_empty() || self.walls.len() == map.get_walls_iter().count()
+    }
+
+    pub fn wall_seen(&self, wall: usize) -> bool {
+        self.walls.get(wall).copied().unwrap_or(false)
+    }
+
+    pub fn object_seen(&self, object: usize) -> bool {
+        self.objects.get(object).copied().unwrap_or(false)
+    }
+
+    pub fn exit_seen(&self) -> bool {
+        self.exit
+    }
+
+    /// Explored regions within `EXPLORE_DRAW_DISTANCE` of the player,
+    /// in the map's view. Each is a fan of points, starting with its center.
+    pub fn regions_in_view(&self, map: &HyperMap) -> Vec<Vec<HyperPoint>> {
+        let frames = Frames::new(map);
+        self.regions
+            .iter()
+            .filter(|region| {
+                frames.in_view(region.anchor, &region.fan[0]).distance_to_origin() < EXPLORE_DRAW_DISTANCE
+            })
+            .map(|region| region.fan.iter().map(|point| frames.in_view(region.anchor, point)).collect())
+            .collect()
+    }
+}
+
+/// Isometries from the frames regions are kept in to the view.
+struct Frames {
+    walls: Vec<Matrix3<f64>>,
+    world: Matrix3<f64>,
+}
+
+impl Frames {
+    fn new(map: &HyperMap) -> Frames {
+        Frames {
+            walls: map.get_walls_iter().map(wall_frame).collect(),
+            world: *map.get_transform(),
+        }
+    }
+
+    fn to_view(&self, anchor: Option<usize>) -> Matrix3<f64> {
+        anchor.and_then(|i| self.walls.get(i)).copied().unwrap_or(self.world)
+    }
+
+    fn in_view(&self, anchor: Option<usize>, point: &HyperPoint) -> HyperPoint {
+        let mut point = *point;
+        point.transform(&self.to_view(anchor));
+        point.normalize();
+        point
+    }
+}
+
+/// Isometry from the frame of a wall, with its beginning at the origin
+/// and its end on the x axis, to the view.
+fn wall_frame(wall: &HyperWall) -> Matrix3<f64> {
+    let centering = wall.beginning.centering_isometry();
+    let mut end = wall.end;
+    end.transform(&centering);
+    HyperPoint::inverse_isometry(&centering) * HyperPoint::rotation_matrix(end.angle())
+}
+
+/// Walls seen through from the origin of the view.
+struct Sight<'a> {
+    walls: &'a [Segment],
+}
+
+impl Sight<'_> {
+    /// Whether a point of the Klein disk is within `EXPLORE_RADIUS`
+    /// and no wall stands between it and the origin, besides `own`,
+    /// the one it is on.
+    fn sees(&self, point: [f64; 2], own: Option<usize>) -> bool {
+        let radius = EXPLORE_RADIUS.tanh();
+        point[0] * point[0] + point[1] * point[1] < radius * radius
+            && self
+                .walls
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| Some(i) != own)
+                .all(|(_, &wall)| crossing(point, wall).is_none_or(|t| t > 1. - 1e-9))
+    }
+
+    /// Where the line of sight in the given direction ends,
+    /// on the first wall or `EXPLORE_RADIUS` away.
+    fn ray(&self, angle: f64) -> [f64; 2] {
+        let radius = EXPLORE_RADIUS.tanh();
+        let far = [radius * angle.cos(), radius * angle.sin()];
+        let t = self
+            .walls
+            .iter()
+            .filter_map(|&wall| crossing(far, wall))
+            .fold(1., f64::min);
+        [far[0] * t, far[1] * t]
+    }
+}
+
+/// Where the segment from the origin to `to` crosses a wall,
+/// as a fraction of its length.
+fn crossing(to: [f64; 2], (a, b): Segment) -> Option<f64> {
+    let d = [b[0] - a[0], b[1] - a[1]];
+    let denom = to[0] * d[1] - to[1] * d[0];
+    if denom.abs() < 1e-12 {
+        return None;
+    }
+    let t = (a[0] * d[1] - a[1] * d[0]) / denom;
+    let u = (a[0] * to[1] - a[1] * to[0]) / denom;
+    if (0. ..=1.).contains(&t) && (0. ..=1.).contains(&u) {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+fn klein(point: &HyperPoint) -> [f64; 2] {
+    let point = KleinPoint::from(point);
+    [point.0.x, point.0.y]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::hyperpoint::HyperObject;
+
+    fn wall(from: (f64, f64), to: (f64, f64)) -> HyperWall {
+        HyperWall {
+            beginning: HyperPoint::new(from.0, from.1),
+            end: HyperPoint::new(to.0, to.1),
+            texture: String::new(),
+            height: WALL_HEIGHT as f64,
+        }
+    }
+
+    #[test]
+    fn test_line_of_sight() {
+        // A wall ahead hides another one behind it, and the pickup between them.
+        let walls = vec![
+            wall((0.3, -0.5), (0.3, 0.5)),
+            wall((0.6, -0.1), (0.6, 0.1)),
+            wall((-0.3, -0.1), (-0.3, 0.1)),
+        ];
+        let object = HyperObject {
+            position: HyperPoint::new(0.45, 0.),
+            active: true,
+            size: 0.02,
+        };
+        let mut map = HyperMap::new_with(walls, vec![object]);
+        let mut exploration = Exploration::new();
+        exploration.look(&map, None);
+        assert!(exploration.wall_seen(0));
+        assert!(!exploration.wall_seen(1));
+        assert!(exploration.wall_seen(2));
+        assert!(!exploration.object_seen(0));
+
+        // Walking around the wall shows them, and what was seen stays seen.
+        map.translate(-0.45, -1.);
+        exploration.look(&map, None);
+        assert!(exploration.wall_seen(1));
+        assert!(exploration.object_seen(0));
+        assert!(exploration.wall_seen(2));
+        assert_eq!(exploration.regions_in_view(&map).len(), 2);
+    }
+
+    #[test]
+    fn test_far_from_start() {
+        // A corridor 25 long, walked down to its end.
+        let side = |x: f64, y: f64| {
+            let mut point = HyperPoint::new(0., y);
+            point.transform(&HyperPoint::translation_matrix(x, 0.));
+            (point.0.x, point.0.y)
+        };
+        let walls = (0..50)
+            .flat_map(|i| {
+                let (x, next) = (0.5 * i as f64, 0.5 * (i + 1) as f64);
+                vec![wall(side(x, -0.2), side(next, -0.2)), wall(side(x, 0.2), side(next, 0.2))]
+            })
+            .collect();
+        let mut map = HyperMap::new_with(walls, vec![]);
+        let mut exploration = Exploration::new();
+        for _ in 0..200 {
+            map.translate(-0.1, 0.);
+            exploration.look(&map, None);
+        }
+        // The region just explored is still around the player, and the
+        // ones explored near the start are too far away to be drawn.
+        let regions = exploration.regions_in_view(&map);
+        assert!(regions.len() < exploration.regions.len());
+        let closest = regions
+            .iter()
+            .map(|fan| fan[0].distance_to_origin())
+            .fold(f64::INFINITY, f64::min);
+        assert!(closest < EXPLORE_SPACING);
+        assert!(exploration.wall_seen(80));
+        assert!(!exploration.wall_seen(98));
+    }
+}
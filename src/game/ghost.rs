@@ -0,0 +1,107 @@
+use std::fs;
+use std::path::PathBuf;
+
+use nalgebra::Matrix3;
+use serde::{Deserialize, Serialize};
+
+use crate::constants::*;
+use crate::utils::hyperpoint::*;
+use crate::utils::point::Point;
+
+/// Player's pose at a given moment of a run.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GhostSample {
+    /// Seconds since the start of the run.
+    pub time: f64,
+    /// Isometry mapping the player's view to the world,
+    /// i.e. the player's pose in world coordinates.
+    pub pose: Matrix3<f64>,
+}
+
+/// A recorded run through a map, replayed as a ghost.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct GhostRun {
+    /// Time it took to finish the run, in seconds.
+    pub time: f64,
+    pub samples: Vec<GhostSample>,
+}
+
+impl GhostRun {
+    pub fn new() -> GhostRun {
+        GhostRun::default()
+    }
+
+    /// Loads the best run recorded on the given map, if there is one.
+    pub fn load_best(map_name: &str) -> Option<GhostRun> {
+        let content = fs::read_to_string(Self::path(map_name)).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    /// Stores the run as the best one on the given map.
+    pub fn save_best(&self, map_name: &str) -> std::io::Result<()> {
+        fs::create_dir_all(GHOST_DIR)?;
+        let content = serde_json::to_string(self)?;
+        fs::write(Self::path(map_name), content)
+    }
+
+    /// File holding the best run of a map.
+    fn path(map_name: &str) -> PathBuf {
+        let file_name: String = map_name
+            .chars()
+            .map(|c| if c.is_alphanumeric() || c == '.' { c } else { '_' })
+            .collect();
+        PathBuf::from(GHOST_DIR).join(format!("{}.json", file_name))
+    }
+
+    /// Records the player's pose. Samples closer together
+    /// than `GHOST_SAMPLE_INTERVAL` are skipped.
+    pub fn record(&mut self, time: f64, pose: Matrix3<f64>) {
+        match self.samples.last() {
+            Some(last) if time - last.time < GHOST_SAMPLE_INTERVAL => {}
+            _ => self.samples.push(GhostSample { time, pose }),
+        }
+    }
+
+    /// Ghost's position in world coordinates at the given time,
+    /// interpolated between the neighbouring samples.
+    pub fn position_at(&self, time: f64) -> Option<HyperPoint> {
+        let next = self.samples.iter().position(|s| s.time >= time);
+        let (a, b) = match next {
+            Some(0) => (self.samples.first()?, self.samples.first()?),
+            Some(i) => (&self.samples[i - 1], &self.samples[i]),
+            None => (self.samples.last()?, self.samples.last()?),
+        };
+        let t = if b.time > a.time {
+            (time - a.time) / (b.time - a.time)
+        } else {
+            0.
+        };
+
+        let origin = HyperPoint::new_at_origin().0;
+        let from = a.pose * origin;
+        let to = b.pose * origin;
+        let mut position = HyperPoint(from + (to - from) * t);
+        position.normalize();
+        Some(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_position_at() {
+        let mut run = GhostRun::new();
+        run.record(0., Matrix3::identity());
+        run.record(1., HyperPoint::translation_matrix(0.5, 0.));
+
+        let start = run.position_at(0.).unwrap();
+        assert!(start.distance_to_origin() < 1e-9);
+
+        let end = run.position_at(5.).unwrap();
+        assert!((end.distance_to_origin() - 0.5).abs() < 1e-9);
+
+        let halfway = run.position_at(0.5).unwrap();
+        assert!((halfway.distance_to_origin() - 0.25).abs() < 1e-3);
+    }
+}
@@ -1,47 +1,51 @@
-use crate::utils::{hyperpoint::*, poincarepoint::*};
-use serde::{Deserialize};
+use super::mapfile::{MapFile, MapInfo};
+use super::maperror::MapLoadError;
+use crate::utils::hyperpoint::*;
+use nalgebra::Matrix3;
+use serde::{Deserialize, Serialize};
 
 /// Represents the map in the Minkowski hyperboloid model.
+#[derive(Serialize, Deserialize)]
 pub struct HyperMap {
     /// Walls of the map.
     walls: Vec<HyperWall>,
     objects: Vec<HyperObject>,
-}
-
-#[derive(Deserialize)]
-struct PoincareMap {
-    walls: Vec<PoincareWall>,
-    objects: Vec<PoincareObject>,
+    /// Isometry accumulated from all rotations and translations,
+    /// mapping the map's original (world) coordinates to the current view.
+    transform: Matrix3<f64>,
+    #[serde(default)]
+    info: MapInfo,
 }
 
 impl HyperMap {
     /// Creates a new map from the given JSON string.
     ///
     /// # Parameters
-    ///    - `map_string`:	A JSON representation of the map, an array of PoincareWalls.
-    pub fn new(map_string: &str) -> HyperMap {
-        // Parse JSON to PoincareMap.
-        let map: PoincareMap = serde_json::from_str(map_string).unwrap();
-
-        // Then transform them into the Minkowski Hyperboloid as internal representation.
+    ///    - `map_string`:	A JSON representation of the map, versioned or legacy.
+    pub fn new(map_string: &str) -> Result<HyperMap, MapLoadError> {
+        // Transform the map into the Minkowski Hyperboloid as internal representation.
         // This is done so it's easier to do transformations on the points
-        let transformed_walls: Vec<HyperWall> = map.walls.into_iter().map(|w| w.into()).collect();
-        let transformed_objects: Vec<HyperObject> =
-            map.objects.into_iter().map(|o| o.into()).collect();
-
-        HyperMap {
-            walls: transformed_walls,
-            objects: transformed_objects,
-        }
+        MapFile::from_json(map_string).map(|file| file.to_map())
     }
 
     pub fn new_with(walls: Vec<HyperWall>, objects: Vec<HyperObject>,) -> HyperMap {
         HyperMap {
             walls: walls,
             objects: objects,
+            transform: Matrix3::identity(),
+            info: MapInfo::default(),
         }
     }
 
+    /// Returns the start pose, rules and other metadata of the map.
+    pub fn get_info(&self) -> &MapInfo {
+        &self.info
+    }
+
+    pub fn set_info(&mut self, info: MapInfo) {
+        self.info = info;
+    }
+
     /// Returns iterator of HyperWall references.
     pub fn get_walls_iter(&self) -> impl Iterator<Item = &HyperWall> {
         self.walls.iter()
@@ -57,27 +61,61 @@ impl HyperMap {
         self.objects.iter()
     }
 
+    /// Returns the isometry mapping world coordinates to the current view.
+    /// The player stands at the origin of the view, so its inverse
+    /// is the player's pose in the world.
+    pub fn get_transform(&self) -> &Matrix3<f64> {
+        &self.transform
+    }
+
     /// Rotate all walls around an origin.
     pub fn rotate(&mut self, step: f64) {
+        self.apply(&HyperPoint::rotation_matrix(step));
+    }
+
+    /// Move all walls along the x and y axes.
+    pub fn translate(&mut self, x: f64, y: f64) {
+        self.apply(&HyperPoint::translation_matrix(x, y));
+    }
+
+    /// Moves the view so that the isometry from world coordinates becomes `transform`.
+    pub fn set_transform(&mut self, transform: &Matrix3<f64>) {
+        self.apply(&(transform * HyperPoint::inverse_isometry(&self.transform)));
+    }
+
+    /// Apply an isometry to all walls and objects.
+    pub fn apply(&mut self, isometry: &Matrix3<f64>) {
         for wall in &mut self.walls {
-            wall.beginning.rotate(step);
-            wall.end.rotate(step);
+            wall.beginning.transform(isometry);
+            wall.end.transform(isometry);
         }
 
         for object in &mut self.objects {
-            object.position.rotate(step);
+            object.position.transform(isometry);
         }
+
+        self.transform = isometry * self.transform;
     }
+}
 
-    /// Move all walls along the x and y axes.
-    pub fn translate(&mut self, x: f64, y: f64) {
-        for wall in &mut self.walls {
-            wall.beginning.translate(x, y);
-            wall.end.translate(x, y);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_malformed_json() {
+        let result = HyperMap::new("{\"walls\": [], \"objects\": [");
+        match result {
+            Err(MapLoadError::Syntax(message)) => assert!(message.contains("line 1")),
+            _ => panic!("expected a syntax error"),
         }
+    }
 
-        for object in &mut self.objects {
-            object.position.translate(x, y);
+    #[test]
+    fn test_missing_field() {
+        let result = HyperMap::new("{\"walls\": [{\"beginning\": [0.1, 0.1], \"end\": [0.2, 0.1]}], \"objects\": []}");
+        match result {
+            Err(MapLoadError::Syntax(message)) => assert!(message.contains("texture")),
+            _ => panic!("expected a syntax error"),
         }
     }
 }
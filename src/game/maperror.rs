@@ -0,0 +1,76 @@
+use std::fmt;
+
+/// Identifies an element of a map file, so designers can find it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ElementRef {
+    /// Tag name, e.g. `line`.
+    pub tag: String,
+    /// Value of the `id` attribute, if the element has one.
+    pub id: Option<String>,
+    /// Position of the element in the document, counting from 1.
+    pub index: usize,
+}
+
+impl fmt::Display for ElementRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.id {
+            Some(id) => write!(f, "<{} id=\"{}\"> (element {})", self.tag, id, self.index),
+            None => write!(f, "<{}> (element {})", self.tag, self.index),
+        }
+    }
+}
+
+/// Reason a map could not be loaded.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MapLoadError {
+    /// The file could not be read.
+    Io(String),
+    /// The document is not valid SVG or JSON. The message includes
+    /// the line and column, when the parser reports them.
+    Syntax(String),
+    /// An element lacks an attribute the loader needs.
+    MissingAttribute { element: ElementRef, attribute: String },
+    /// An attribute's value could not be understood.
+    InvalidAttribute {
+        element: ElementRef,
+        attribute: String,
+        value: String,
+    },
+    /// A wall of a map drawn in the Poincaré model is not a geodesic.
+    NotGeodesic { element: ElementRef },
+}
+
+impl fmt::Display for MapLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapLoadError::Io(message) => write!(f, "{}", message),
+            MapLoadError::Syntax(message) => write!(f, "Syntax error: {}", message),
+            MapLoadError::MissingAttribute { element, attribute } => {
+                write!(f, "{}: missing attribute {}", element, attribute)
+            }
+            MapLoadError::InvalidAttribute {
+                element,
+                attribute,
+                value,
+            } => write!(f, "{}: invalid {}=\"{}\"", element, attribute, value),
+            MapLoadError::NotGeodesic { element } => {
+                write!(f, "{}: wall is not a geodesic of the Poincaré disk", element)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MapLoadError {}
+
+/// Something in a map file which was skipped, but did not stop it from loading.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MapLoadWarning {
+    pub element: ElementRef,
+    pub message: String,
+}
+
+impl fmt::Display for MapLoadWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.element, self.message)
+    }
+}
@@ -0,0 +1,352 @@
+use std::fs;
+use std::path::Path;
+
+use nalgebra::Matrix3;
+use serde::{Deserialize, Serialize};
+
+use super::campaign::CompletionRule;
+use super::hypermap::HyperMap;
+use super::maperror::{MapLoadError, MapLoadWarning};
+use super::maps;
+use super::svgloader::Model;
+use super::svgwriter;
+use crate::utils::hyperpoint::*;
+use crate::utils::poincarepoint::*;
+
+/// Version of the map schema written by this build.
+pub const MAP_VERSION: u32 = 1;
+
+/// A map in the versioned JSON schema. Coordinates are
+/// in the Poincaré disk model, like the legacy JSON format.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MapFile {
+    pub version: u32,
+    #[serde(flatten)]
+    pub info: MapInfo,
+    /// Names of the textures the walls use.
+    #[serde(default)]
+    pub textures: Vec<String>,
+    pub walls: Vec<PoincareWall>,
+    #[serde(default)]
+    pub objects: Vec<PoincareObject>,
+}
+
+/// Metadata of a map, besides its walls and objects.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MapInfo {
+    pub title: String,
+    pub author: String,
+    pub start: StartPose,
+    /// What needs to be done to win, unless the campaign says otherwise.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule: Option<CompletionRule>,
+    /// Where the player has to go, in world coordinates.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit: Option<PoincarePoint>,
+    /// Time to beat, in seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub par_time: Option<f64>,
+    /// Point lights, in world coordinates.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub lights: Vec<Light>,
+}
+
+impl MapInfo {
+    /// Checks that the rule can be met on the map.
+    pub fn check(&self) -> Result<(), String> {
+        if self.rule == Some(CompletionRule::ReachExit) && self.exit.is_none() {
+            return Err("the rule is to reach the exit, but there is none".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Where the player starts.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StartPose {
+    pub position: PoincarePoint,
+    /// Angle the player faces, from the x axis towards the y axis, in radians.
+    pub heading: f64,
+}
+
+impl StartPose {
+    /// Isometry taking the map from world coordinates to the view
+    /// of a player standing at the start, facing +x.
+    pub fn isometry(&self) -> Matrix3<f64> {
+        HyperPoint::rotation_matrix(-self.heading)
+            * HyperPoint::from(self.position.clone()).centering_isometry()
+    }
+}
+
+impl Default for StartPose {
+    fn default() -> Self {
+        StartPose {
+            position: PoincarePoint::new(0., 0.),
+            heading: 0.,
+        }
+    }
+}
+
+/// A point light. Its brightness falls off with the circumference
+/// of the circle it lights, as 1 / sinh of the distance.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Light {
+    pub position: PoincarePoint,
+    /// Brightness at `LIGHT_RADIUS` from the light, and closer.
+    #[serde(default = "default_intensity")]
+    pub intensity: f64,
+    /// Color as RGB from 0 to 255.
+    #[serde(default = "default_light_color")]
+    pub color: [u8; 3],
+}
+
+pub fn default_intensity() -> f64 {
+    1.
+}
+
+pub fn default_light_color() -> [u8; 3] {
+    [255, 255, 255]
+}
+
+/// The legacy JSON format, without version nor metadata.
+#[derive(Serialize, Deserialize)]
+struct LegacyMap {
+    walls: Vec<PoincareWall>,
+    objects: Vec<PoincareObject>,
+}
+
+/// Formats the converter can write.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MapFormat {
+    /// The versioned JSON schema.
+    Json,
+    /// JSON with walls and objects only.
+    LegacyJson,
+    Svg(Model),
+}
+
+impl MapFormat {
+    /// Reads a format name given on the command line.
+    pub fn parse(name: &str) -> Option<MapFormat> {
+        match name {
+            "json" => Some(MapFormat::Json),
+            "legacy-json" => Some(MapFormat::LegacyJson),
+            "svg" | "klein-svg" => Some(MapFormat::Svg(Model::Klein)),
+            "poincare-svg" => Some(MapFormat::Svg(Model::Poincare)),
+            _ => None,
+        }
+    }
+
+    /// Format implied by the extension of a file.
+    pub fn from_path(path: &str) -> Option<MapFormat> {
+        match Path::new(path).extension()?.to_str()? {
+            "json" => Some(MapFormat::Json),
+            "svg" => Some(MapFormat::Svg(Model::Klein)),
+            _ => None,
+        }
+    }
+}
+
+impl MapFile {
+    /// Reads a map in any format: SVG, legacy JSON or the versioned schema.
+    /// Also returns warnings about the elements of SVG maps which were skipped.
+    pub fn parse(content: &str) -> Result<(MapFile, Vec<MapLoadWarning>), MapLoadError> {
+        if content.trim_start().starts_with('{') {
+            MapFile::from_json(content).map(|file| (file, vec![]))
+        } else {
+            maps::load_str(content).map(|(map, warnings)| (MapFile::from_map(&map), warnings))
+        }
+    }
+
+    /// Reads a map in the versioned schema, or in the legacy JSON format.
+    pub fn from_json(content: &str) -> Result<MapFile, MapLoadError> {
+        let syntax = |e: serde_json::Error| MapLoadError::Syntax(e.to_string());
+        let value: serde_json::Value = serde_json::from_str(content).map_err(syntax)?;
+        if value.get("version").is_none() {
+            let legacy: LegacyMap = serde_json::from_value(value).map_err(syntax)?;
+            return Ok(MapFile::new(legacy.walls, legacy.objects));
+        }
+
+        let file: MapFile = serde_json::from_value(value).map_err(syntax)?;
+        if file.version > MAP_VERSION {
+            return Err(MapLoadError::Syntax(format!(
+                "map version {} is newer than the supported version {}",
+                file.version, MAP_VERSION
+            )));
+        }
+        file.info.check().map_err(MapLoadError::Syntax)?;
+        Ok(file)
+    }
+
+    /// A map without metadata, listing the textures its walls use.
+    pub fn new(walls: Vec<PoincareWall>, objects: Vec<PoincareObject>) -> MapFile {
+        let mut textures: Vec<String> = walls.iter().map(|w| w.texture.clone()).collect();
+        textures.sort();
+        textures.dedup();
+        MapFile {
+            version: MAP_VERSION,
+            info: MapInfo::default(),
+            textures,
+            walls,
+            objects,
+        }
+    }
+
+    /// The map in world coordinates, wherever the player moved it.
+    pub fn from_map(map: &HyperMap) -> MapFile {
+        let to_world = HyperPoint::inverse_isometry(map.get_transform());
+        let world = |point: &HyperPoint| {
+            let mut point = *point;
+            point.transform(&to_world);
+            point
+        };
+        let walls = map
+            .get_walls_iter()
+            .map(|wall| {
+                let mut wall = wall.clone();
+                wall.beginning = world(&wall.beginning);
+                wall.end = world(&wall.end);
+                PoincareWall::from(wall)
+            })
+            .collect();
+        let objects = map
+            .get_objects_iter()
+            .map(|object| {
+                let mut object = object.clone();
+                object.position = world(&object.position);
+                PoincareObject::from(&object)
+            })
+            .collect();
+        MapFile {
+            info: map.get_info().clone(),
+            ..MapFile::new(walls, objects)
+        }
+    }
+
+    /// Converts the map into the internal representation.
+    pub fn to_map(&self) -> HyperMap {
+        let mut map = HyperMap::new_with(
+            self.walls.iter().cloned().map(HyperWall::from).collect(),
+            self.objects.iter().cloned().map(HyperObject::from).collect(),
+        );
+        map.set_info(self.info.clone());
+        map
+    }
+
+    /// Writes the map in the given format. The legacy JSON format loses
+    /// the metadata, and SVG the list of textures.
+    pub fn write(&self, format: MapFormat) -> String {
+        match format {
+            MapFormat::Json => serde_json::to_string_pretty(self).unwrap(),
+            MapFormat::LegacyJson => serde_json::to_string_pretty(&LegacyMap {
+                walls: self.walls.clone(),
+                objects: self.objects.clone(),
+            })
+            .unwrap(),
+            MapFormat::Svg(model) => svgwriter::write_map(&self.to_map(), model, &Matrix3::identity()),
+        }
+    }
+}
+
+/// Converts the map file at `input` into `output`, in the given format
+/// or else the one implied by the extension of `output`.
+/// Returns warnings about the elements which were skipped.
+pub fn convert(
+    input: &str,
+    output: &str,
+    format: Option<MapFormat>,
+) -> Result<Vec<MapLoadWarning>, String> {
+    let format = format
+        .or_else(|| MapFormat::from_path(output))
+        .ok_or_else(|| format!("Cannot tell the format of {}, use --format", output))?;
+    let content = fs::read_to_string(input).map_err(|e| format!("{}: {}", input, e))?;
+    let (file, warnings) = MapFile::parse(&content).map_err(|e| format!("{}: {}", input, e))?;
+    fs::write(output, file.write(format)).map_err(|e| format!("{}: {}", output, e))?;
+    Ok(warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_versioned_schema() {
+        let legacy = MapFile::parse(include_str!("../../assets/demotext.json")).unwrap().0;
+        let mut file = legacy.clone();
+        file.info.title = "Demo".to_string();
+        file.info.author = "Someone".to_string();
+        file.info.start.heading = 1.5;
+        file.info.rule = Some(CompletionRule::Collect(1));
+
+        let read = MapFile::parse(&file.write(MapFormat::Json)).unwrap().0;
+        assert_eq!(read.version, MAP_VERSION);
+        assert_eq!(read.info.title, "Demo");
+        assert_eq!(read.info.author, "Someone");
+        assert_eq!(read.info.start.heading, 1.5);
+        assert_eq!(read.info.rule, Some(CompletionRule::Collect(1)));
+        assert!(read.info.par_time.is_none());
+        assert_eq!(read.textures, vec!["CONCRETE", "WALL"]);
+        assert_eq!(read.walls.len(), legacy.walls.len());
+
+        let newer = file.write(MapFormat::Json).replacen("\"version\": 1", "\"version\": 99", 1);
+        assert!(matches!(MapFile::parse(&newer), Err(MapLoadError::Syntax(_))));
+
+        file.info.rule = Some(CompletionRule::ReachExit);
+        let without_exit = file.write(MapFormat::Json);
+        assert!(matches!(MapFile::parse(&without_exit), Err(MapLoadError::Syntax(_))));
+    }
+
+    #[test]
+    fn test_start_isometry() {
+        let start = StartPose {
+            position: PoincarePoint::new(0.3, -0.2),
+            heading: 0.7,
+        };
+        let isometry = start.isometry();
+        let mut position = HyperPoint::from(start.position.clone());
+        position.transform(&isometry);
+        assert!((position.0 - HyperPoint::new(0., 0.).0).norm() < 1e-9);
+
+        // A point ahead of the start ends up on the +x axis.
+        let ahead = PoincarePoint::new(0.3 + 1e-5 * 0.7f64.cos(), -0.2 + 1e-5 * 0.7f64.sin());
+        let mut ahead = HyperPoint::from(ahead);
+        ahead.transform(&isometry);
+        assert!(ahead.0.x > 0. && ahead.0.y.abs() < 1e-3 * ahead.0.x);
+    }
+
+    #[test]
+    fn test_conversions() {
+        let mut original = MapFile::parse(include_str!("../../assets/map3.svg")).unwrap().0;
+        original.info.start = StartPose {
+            position: PoincarePoint::new(0.2, -0.1),
+            heading: 1.,
+        };
+        original.info.rule = Some(CompletionRule::ReachExit);
+        original.info.exit = Some(PoincarePoint::new(-0.4, 0.3));
+        original.info.par_time = Some(40.);
+        for &format in &[
+            MapFormat::Json,
+            MapFormat::LegacyJson,
+            MapFormat::Svg(Model::Klein),
+            MapFormat::Svg(Model::Poincare),
+        ] {
+            let converted = MapFile::parse(&original.write(format)).unwrap().0;
+            assert_eq!(converted.walls.len(), original.walls.len());
+            assert_eq!(converted.objects.len(), original.objects.len());
+            let (a, b) = (&original.walls[0].end.0, &converted.walls[0].end.0);
+            assert!((a - b).norm() < 1e-3, "{:?}: {} != {}", format, a, b);
+
+            if format != MapFormat::LegacyJson {
+                let (info, read) = (&original.info, &converted.info);
+                assert_eq!(read.rule, info.rule);
+                assert_eq!(read.par_time, info.par_time);
+                assert!((read.start.heading - info.start.heading).abs() < 1e-6);
+                let (a, b) = (&info.start.position.0, &read.start.position.0);
+                assert!((a - b).norm() < 1e-6, "{:?}: {} != {}", format, a, b);
+                let (a, b) = (&info.exit.as_ref().unwrap().0, &read.exit.as_ref().unwrap().0);
+                assert!((a - b).norm() < 1e-6, "{:?}: {} != {}", format, a, b);
+            }
+        }
+    }
+}
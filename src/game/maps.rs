@@ -0,0 +1,108 @@
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+use super::hypermap::HyperMap;
+use super::maperror::{MapLoadError, MapLoadWarning};
+use super::svgloader::load_map_with_warnings;
+use crate::constants::*;
+
+/// Maps bundled into the executable, by file name.
+const BUILTIN_MAPS: [(&str, &str); 5] = [
+    ("demotext.json", include_str!("../../assets/demotext.json")),
+    ("demolarge.json", include_str!("../../assets/demolarge.json")),
+    ("map.svg", include_str!("../../assets/map.svg")),
+    ("map2.svg", include_str!("../../assets/map2.svg")),
+    ("map3.svg", include_str!("../../assets/map3.svg")),
+];
+
+/// Loads a map, either one from disk by its path, or one of the bundled
+/// ones by its file name, along with warnings about the elements which
+/// were skipped. A file on disk wins over the bundled map of the same name.
+pub fn load_with_warnings(name: &str) -> Result<(HyperMap, Vec<MapLoadWarning>), MapLoadError> {
+    match load_builtin(name) {
+        Some(map) if is_builtin(name) => Ok((map, vec![])),
+        _ => load_file(name),
+    }
+}
+
+/// Loads a map like `load_with_warnings`, without the warnings.
+pub fn load(name: &str) -> Result<HyperMap, MapLoadError> {
+    load_with_warnings(name).map(|(map, _)| map)
+}
+
+/// Loads one of the bundled maps by its file name.
+pub fn load_builtin(name: &str) -> Option<HyperMap> {
+    let (_, content) = BUILTIN_MAPS.iter().find(|(n, _)| *n == name)?;
+    load_str(content).ok().map(|(map, _)| map)
+}
+
+/// Whether the name refers to one of the bundled maps,
+/// and there is no file of that name on disk to load instead.
+pub fn is_builtin(name: &str) -> bool {
+    BUILTIN_MAPS.iter().any(|(n, _)| *n == name) && !Path::new(name).exists()
+}
+
+/// Loads a map from disk.
+pub fn load_file(path: &str) -> Result<(HyperMap, Vec<MapLoadWarning>), MapLoadError> {
+    let content =
+        fs::read_to_string(path).map_err(|e| MapLoadError::Io(format!("{}: {}", path, e)))?;
+    load_str(&content)
+}
+
+/// Loads a map, detecting its format from the content.
+/// SVG maps are read in the Klein model, JSON maps in the Poincare model.
+/// Also returns warnings about the elements of SVG maps which were skipped.
+pub fn load_str(content: &str) -> Result<(HyperMap, Vec<MapLoadWarning>), MapLoadError> {
+    if content.trim_start().starts_with('{') {
+        HyperMap::new(content).map(|map| (map, vec![]))
+    } else {
+        load_map_with_warnings(content)
+    }
+}
+
+/// Watches a map file on disk, so it can be reloaded when it is saved.
+pub struct MapWatcher {
+    path: String,
+    modified: Option<SystemTime>,
+    /// Seconds since the file was last checked.
+    since_check: f64,
+}
+
+impl MapWatcher {
+    pub fn new(path: &str) -> MapWatcher {
+        MapWatcher {
+            path: path.to_string(),
+            modified: Self::modified(path),
+            since_check: 0.,
+        }
+    }
+
+    /// Checks the file every `RELOAD_INTERVAL` seconds.
+    /// Returns true once it has been modified since the last check.
+    pub fn poll(&mut self, dt: f64) -> bool {
+        self.since_check += dt;
+        if self.since_check < RELOAD_INTERVAL {
+            return false;
+        }
+        self.since_check = 0.;
+
+        // Editors often replace the file on save, so it may briefly be missing.
+        let modified = Self::modified(&self.path);
+        if modified.is_some() && modified != self.modified {
+            self.modified = modified;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Reloads the watched map.
+    pub fn load(&self) -> Result<(HyperMap, Vec<MapLoadWarning>), MapLoadError> {
+        load_file(&self.path)
+    }
+
+    fn modified(path: &str) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+}
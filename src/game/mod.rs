@@ -1,11 +1,29 @@
+pub mod campaign;
+pub mod editor;
+pub mod exploration;
+pub mod ghost;
 pub mod hypermap;
+pub mod maperror;
+pub mod mapfile;
+pub mod maps;
+pub mod save;
 pub mod svgloader;
+pub mod svgtransform;
+pub mod svgwriter;
+pub mod texturepack;
+pub mod tiling;
+pub mod validator;
 
 use macroquad::prelude::*;
 use macroquad::ui::*;
+use nalgebra::Matrix3;
 
 use crate::constants::*;
+use crate::game::campaign::*;
+use crate::game::exploration::Exploration;
+use crate::game::ghost::*;
 use crate::game::hypermap::*;
+use crate::utils::hyperpoint::*;
 use crate::utils::kleinpoint::*;
 use crate::utils::point::Point;
 
@@ -13,12 +31,71 @@ use crate::utils::point::Point;
 pub struct Game {
     /// The map of our virtual world
     pub map: HyperMap,
+    /// Name of the map, used to look up its best run.
+    pub map_name: String,
+    /// What needs to be done to win.
+    pub rule: CompletionRule,
+    /// Seconds since the start of the run.
+    pub elapsed: f64,
+    /// Whether the run is over and the clock has stopped.
+    finished: bool,
+    /// The current run, recorded as it goes.
+    recording: GhostRun,
+    /// Best run on this map so far, replayed as a ghost.
+    best_run: Option<GhostRun>,
+    /// Whether the finished run beat the best one from before it.
+    new_best: bool,
+    /// Why the best run could not be saved, shown in the HUD.
+    best_run_error: Option<String>,
+    /// Whether the player's flashlight is on.
+    pub flashlight: bool,
+    /// What the player has seen so far, for the automap.
+    pub exploration: Exploration,
+    /// Whether `exploration` is kept up to date, for the fog of war.
+    pub fog_of_war: bool,
 }
 
 impl Game {
-    /// Initializes a new game based on a given map and player.
-    pub fn new(map: HyperMap) -> Game {
-        Game { map }
+    /// Initializes a new game based on a given map and player,
+    /// who stands at the map's start. `rule` overrides the one
+    /// the map sets, as campaign levels do.
+    pub fn new(mut map: HyperMap, map_name: &str, rule: Option<CompletionRule>) -> Game {
+        let rule = rule
+            .or_else(|| map.get_info().rule.clone())
+            .unwrap_or_default();
+        let start = map.get_info().start.isometry();
+        map.apply(&start);
+        Game {
+            map,
+            map_name: map_name.to_string(),
+            rule,
+            elapsed: 0.,
+            finished: false,
+            recording: GhostRun::new(),
+            best_run: GhostRun::load_best(map_name),
+            new_best: false,
+            best_run_error: None,
+            flashlight: false,
+            exploration: Exploration::new(),
+            fog_of_war: false,
+        }
+    }
+
+    /// Replaces the map with a new version of it, e.g. after its file was edited.
+    /// The player keeps its pose, and collected objects which are still
+    /// where they were stay collected. The map is explored anew.
+    pub fn reload(&mut self, mut map: HyperMap) {
+        map.apply(self.map.get_transform());
+        for object in map.get_objects_iter_mut() {
+            let was_collected = self.map.get_objects_iter().any(|old| {
+                !old.active && (old.position.0 - object.position.0).norm() < RELOAD_MATCH_DISTANCE
+            });
+            if was_collected {
+                object.active = false;
+            }
+        }
+        self.map = map;
+        self.exploration = Exploration::new();
     }
 
     /// Rotates the player's viewing angle with the given angle.
@@ -50,9 +127,99 @@ impl Game {
     }
 
     /// Actions taken every frame.
-    pub fn tick(&mut self) {
+    ///
+    /// # Parameters:
+    ///		- `dt`:		Time since the last frame, in seconds.
+    pub fn tick(&mut self, dt: f64) {
         self.solve_wall_collisions();
         self.solve_object_collisions();
+        if self.fog_of_war {
+            self.exploration.look(&self.map, self.exit_position().as_ref());
+        }
+
+        if !self.finished {
+            self.elapsed += dt;
+            self.recording.record(self.elapsed, self.player_pose());
+            if self.is_won() {
+                self.finish_run();
+            } else if self.rule.is_failed(self.elapsed) {
+                // The clock stops, and the run is not recorded.
+                self.finished = true;
+            }
+        }
+    }
+
+    /// Whether the run is over and the clock has stopped.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Whether the run is over without the rule being met.
+    pub fn is_failed(&self) -> bool {
+        self.finished && !self.is_won()
+    }
+
+    /// Whether the run is over and was faster than the best one before it.
+    pub fn is_new_best(&self) -> bool {
+        self.finished && self.new_best
+    }
+
+    /// Time of the best run on this map, if there is one.
+    pub fn best_time(&self) -> Option<f64> {
+        self.best_run.as_ref().map(|run| run.time)
+    }
+
+    /// Whether the completion rule is met.
+    pub fn is_won(&self) -> bool {
+        let total = self.map.get_objects_iter().count();
+        let collected = self.map.get_objects_iter().filter(|o| !o.active).count();
+        self.rule.is_met(collected, total, self.elapsed, self.is_at_exit())
+    }
+
+    /// Time the map sets to beat, if any.
+    pub fn par_time(&self) -> Option<f64> {
+        self.map.get_info().par_time
+    }
+
+    /// Position of the map's exit in the current view, if it has one.
+    pub fn exit_position(&self) -> Option<HyperPoint> {
+        let mut position = HyperPoint::from(self.map.get_info().exit.clone()?);
+        position.transform(self.map.get_transform());
+        Some(position)
+    }
+
+    /// Whether the player stands at the exit.
+    pub fn is_at_exit(&self) -> bool {
+        self.exit_position()
+            .is_some_and(|exit| exit.distance_to_origin() < EXIT_RADIUS)
+    }
+
+    /// Player's pose in world coordinates.
+    pub fn player_pose(&self) -> Matrix3<f64> {
+        HyperPoint::inverse_isometry(self.map.get_transform())
+    }
+
+    /// Position of the best run's ghost in the current view, if there is one.
+    pub fn ghost_position(&self) -> Option<HyperPoint> {
+        let mut position = self.best_run.as_ref()?.position_at(self.elapsed)?;
+        position.transform(self.map.get_transform());
+        Some(position)
+    }
+
+    /// Stops the clock and keeps the run if it beat the best one.
+    fn finish_run(&mut self) {
+        self.finished = true;
+        self.recording.time = self.elapsed;
+
+        let is_best = match &self.best_run {
+            Some(best) => self.recording.time < best.time,
+            None => true,
+        };
+        self.new_best = is_best;
+        if is_best {
+            self.best_run_error = self.recording.save_best(&self.map_name).err().map(|e| e.to_string());
+            self.best_run = Some(self.recording.clone());
+        }
     }
 
     /// Detects collisions with objects, marks them as collected.
@@ -64,7 +231,7 @@ impl Game {
                 let pos_klein = KleinPoint::from(o.position);
                 let distance = pos_klein.distance_to_origin();
 
-                if distance < (OBJECT_RADIUS as f64 + COLLISION_RADIUS) {
+                if distance < (o.size + COLLISION_RADIUS) {
                     o.active = false;
                 }
             });
@@ -171,14 +338,38 @@ impl Game {
     pub fn display_hud(&self) {
         let total_objects = self.map.get_objects_iter().count();
         let inactive_objects = self.map.get_objects_iter().filter(|o| !o.active).count();
-        if inactive_objects == total_objects {
+        if self.is_won() {
             root_ui().label(None, "You won!");
+        } else if self.rule == CompletionRule::ReachExit {
+            root_ui().label(None, "Find the exit...");
         } else {
             root_ui().label(
                 None,
                 &format!("{}/{} found...", inactive_objects, total_objects),
             );
         }
+
+        if let CompletionRule::CollectAllWithin(limit) = self.rule {
+            if self.elapsed > limit && !self.is_won() {
+                root_ui().label(None, "Time's up!");
+            } else {
+                root_ui().label(None, &format!("Limit: {:.0}s", limit));
+            }
+        }
+
+        match &self.best_run {
+            Some(best) => root_ui().label(
+                None,
+                &format!("Time: {:.1}s  Best: {:.1}s", self.elapsed, best.time),
+            ),
+            None => root_ui().label(None, &format!("Time: {:.1}s", self.elapsed)),
+        }
+        if let Some(par_time) = self.par_time() {
+            root_ui().label(None, &format!("Par: {:.1}s", par_time));
+        }
+        if let Some(e) = &self.best_run_error {
+            root_ui().label(None, &format!("Could not save the best run: {}", e));
+        }
     }
 }
 
@@ -210,6 +401,8 @@ fn line_circle_collision_avg(x1: f64, y1: f64, x2: f64, y2: f64, r: f64) -> Opti
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::game::mapfile::*;
+    use crate::utils::poincarepoint::PoincarePoint;
     #[test]
     fn test_line_circle_collision() {
         let expected = (0.5, 0.5);
@@ -224,4 +417,82 @@ mod tests {
         let result = line_circle_collision_avg(-1., 0., 0., 1., 1.);
         assert_eq!(expected, result.unwrap());
     }
+
+    #[test]
+    fn test_start_and_exit() {
+        let mut map = HyperMap::new_with(vec![], vec![]);
+        let start = PoincarePoint::new(0.3, 0.1);
+        map.set_info(MapInfo {
+            start: StartPose {
+                position: start.clone(),
+                heading: 2.,
+            },
+            rule: Some(CompletionRule::ReachExit),
+            exit: Some(PoincarePoint::new(0.31, 0.1)),
+            ..MapInfo::default()
+        });
+        let mut game = Game::new(map, "test_start_and_exit", None);
+        assert_eq!(game.rule, CompletionRule::ReachExit);
+        assert!(game.is_won());
+
+        // The player's pose takes the origin to the start.
+        let mut position = HyperPoint::new(0., 0.);
+        position.transform(&game.player_pose());
+        assert!((position.0 - HyperPoint::from(start).0).norm() < 1e-9);
+
+        game.move_player(0.2);
+        assert!(!game.is_won());
+    }
+
+    #[test]
+    fn test_reload_keeps_pose_and_collected() {
+        let content = include_str!("../../assets/demotext.json");
+        let mut game = Game::new(HyperMap::new(content).unwrap(), "demotext.json", None);
+        game.move_player(0.2);
+        game.rotate_player(1.);
+        game.map.get_objects_iter_mut().next().unwrap().active = false;
+        let wall_before = game.map.get_walls_iter().next().unwrap().beginning;
+
+        game.reload(HyperMap::new(content).unwrap());
+
+        let wall_after = game.map.get_walls_iter().next().unwrap().beginning;
+        assert!((wall_before.0 - wall_after.0).norm() < 1e-9);
+        let active: Vec<bool> = game.map.get_objects_iter().map(|o| o.active).collect();
+        assert_eq!(active, vec![false, true]);
+    }
+
+    #[test]
+    fn test_time_up() {
+        let object = HyperObject {
+            position: HyperPoint::new(0.5, 0.),
+            active: true,
+            size: 0.02,
+        };
+        let map = HyperMap::new_with(vec![], vec![object]);
+        let rule = CompletionRule::CollectAllWithin(1.);
+        let mut game = Game::new(map, "test_time_up", Some(rule));
+        game.tick(0.5);
+        assert!(!game.is_finished());
+        game.tick(1.);
+        assert!(game.is_failed());
+        assert_eq!(game.elapsed, 1.5);
+
+        // The clock has stopped, and the run can't be won any more.
+        game.map.get_objects_iter_mut().next().unwrap().active = false;
+        game.tick(1.);
+        assert_eq!(game.elapsed, 1.5);
+        assert!(game.is_failed());
+    }
+
+    #[test]
+    fn test_tying_the_best_run() {
+        let mut game = Game::new(HyperMap::new_with(vec![], vec![]), "test_tying_the_best_run", None);
+        let mut best = GhostRun::new();
+        best.time = 5.;
+        game.best_run = Some(best);
+        game.elapsed = 5.;
+        game.finish_run();
+        assert!(!game.is_new_best());
+        assert_eq!(game.best_time(), Some(5.));
+    }
 }
@@ -0,0 +1,164 @@
+use std::fs;
+use std::path::PathBuf;
+
+use nalgebra::Matrix3;
+use serde::{Deserialize, Serialize};
+
+use super::campaign::Campaign;
+use super::exploration::Exploration;
+use super::ghost::GhostRun;
+use super::maps;
+use super::Game;
+use crate::constants::*;
+
+/// Version of the save file format. Bump on incompatible changes.
+pub const SAVE_VERSION: u32 = 1;
+
+/// Snapshot of a session, enough to restore play exactly.
+/// The map itself is not stored, only a reference to it.
+#[derive(Serialize, Deserialize)]
+pub struct SaveState {
+    pub version: u32,
+    /// Name of the map the session was played on.
+    pub map_name: String,
+    /// Isometry mapping world coordinates to the player's view.
+    pub view: Matrix3<f64>,
+    /// Whether each object, in map order, has been collected.
+    pub collected: Vec<bool>,
+    /// Seconds since the start of the run.
+    pub elapsed: f64,
+    pub finished: bool,
+    /// The run recorded so far, so a restored run can still become the ghost.
+    pub recording: GhostRun,
+    /// What the player has explored, for the fog of war.
+    #[serde(default)]
+    pub exploration: Exploration,
+}
+
+impl SaveState {
+    /// Captures the state of a running game.
+    pub fn capture(game: &Game) -> SaveState {
+        SaveState {
+            version: SAVE_VERSION,
+            map_name: game.map_name.clone(),
+            view: *game.map.get_transform(),
+            collected: game.map.get_objects_iter().map(|o| !o.active).collect(),
+            elapsed: game.elapsed,
+            finished: game.finished,
+            recording: game.recording.clone(),
+            exploration: game.exploration.clone(),
+        }
+    }
+
+    /// Rebuilds the game from the referenced map and the saved state.
+    pub fn restore(&self) -> Result<Game, String> {
+        let map = maps::load(&self.map_name).map_err(|e| e.to_string())?;
+        let rule = Campaign::builtin().rule_for(&self.map_name);
+        let mut game = Game::new(map, &self.map_name, rule);
+
+        if game.map.get_objects_iter().count() != self.collected.len() {
+            return Err(format!("Save does not match map {}", self.map_name));
+        }
+        game.map
+            .get_objects_iter_mut()
+            .zip(&self.collected)
+            .for_each(|(o, collected)| o.active = !collected);
+        game.map.set_transform(&self.view);
+
+        game.elapsed = self.elapsed;
+        game.finished = self.finished;
+        game.recording = self.recording.clone();
+        // Exploration of walls since added or removed would be drawn wrong.
+        if self.exploration.fits(&game.map) {
+            game.exploration = self.exploration.clone();
+        }
+        Ok(game)
+    }
+
+    /// Writes the state into the given save slot.
+    pub fn save(&self, slot: usize) -> Result<(), String> {
+        fs::create_dir_all(SAVE_DIR).map_err(|e| e.to_string())?;
+        let content = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        fs::write(Self::path(slot), content).map_err(|e| e.to_string())
+    }
+
+    /// Reads the state from the given save slot.
+    pub fn load(slot: usize) -> Result<SaveState, String> {
+        let content = fs::read_to_string(Self::path(slot)).map_err(|e| e.to_string())?;
+        let state: SaveState = serde_json::from_str(&content).map_err(|e| e.to_string())?;
+        if state.version != SAVE_VERSION {
+            return Err(format!(
+                "Save version {} is not supported, expected {}",
+                state.version, SAVE_VERSION
+            ));
+        }
+        Ok(state)
+    }
+
+    /// Whether anything is saved in the given slot.
+    pub fn exists(slot: usize) -> bool {
+        Self::path(slot).exists()
+    }
+
+    /// Empties the given save slot.
+    pub fn delete(slot: usize) -> Result<(), String> {
+        if Self::exists(slot) {
+            fs::remove_file(Self::path(slot)).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// File holding the given save slot.
+    fn path(slot: usize) -> PathBuf {
+        PathBuf::from(SAVE_DIR).join(format!("slot{}.json", slot))
+    }
+}
+
+impl Game {
+    /// Saves the session into the given slot.
+    pub fn save(&self, slot: usize) -> Result<(), String> {
+        SaveState::capture(self).save(slot)
+    }
+
+    /// Restores a session from the given slot.
+    pub fn load(slot: usize) -> Result<Game, String> {
+        SaveState::load(slot)?.restore()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::hyperpoint::HyperPoint;
+    #[test]
+    fn test_capture_restore() {
+        let mut game = Game::new(maps::load_builtin("map3.svg").unwrap(), "map3.svg", None);
+        game.move_player(0.1);
+        game.rotate_player(0.7);
+        game.map.get_objects_iter_mut().next().unwrap().active = false;
+        game.elapsed = 12.5;
+        let walls = game.map.get_walls_iter().count();
+        game.tick(0.);
+        assert!((0..walls).all(|i| !game.exploration.wall_seen(i)));
+        game.fog_of_war = true;
+        game.tick(0.);
+
+        let restored = SaveState::capture(&game).restore().unwrap();
+
+        assert_eq!(restored.elapsed, 12.5);
+        assert!((0..walls).any(|i| game.exploration.wall_seen(i)));
+        assert!((0..walls).all(|i| restored.exploration.wall_seen(i) == game.exploration.wall_seen(i)));
+        assert!(!restored.map.get_objects_iter().next().unwrap().active);
+        let expected: Vec<HyperPoint> = game.map.get_walls_iter().map(|w| w.beginning).collect();
+        let actual: Vec<HyperPoint> = restored.map.get_walls_iter().map(|w| w.beginning).collect();
+        for (e, a) in expected.iter().zip(&actual) {
+            assert!((e.0 - a.0).norm() < 1e-9);
+        }
+
+        let mut state = SaveState::capture(&game);
+        state.exploration = Exploration::new();
+        state.exploration.look(&maps::load_builtin("demotext.json").unwrap(), None);
+        let restored = state.restore().unwrap();
+        assert!((0..walls).all(|i| !restored.exploration.wall_seen(i)));
+    }
+}
@@ -1,73 +1,993 @@
-use svg::node::element::path::{Command, Data};
+use svg::node::element::path::{Command, Data, Position};
 use svg::node::element::tag::*;
+use svg::node::Attributes;
 use svg::parser::Event;
+use nalgebra::Matrix3;
 use super::HyperMap;
+use super::campaign::CompletionRule;
+use super::mapfile::{default_intensity, default_light_color, Light, MapInfo};
+use super::maperror::*;
+use super::svgtransform;
 use crate::utils::*;
 use crate::utils::hyperpoint::*;
 use crate::utils::kleinpoint::*;
+use crate::utils::poincarepoint::*;
 use crate::constants::*;
 
-pub fn load_map(content: &str) -> HyperMap {
-    let walls: Vec<_> = svg::read(content).unwrap().filter_map(|event| {
-        match event {
-            Event::Tag(Line, _, attributes) => {
-                let id = attributes.get("id").unwrap();
-                let stroke = attributes.get("stroke").unwrap();
-                let class = attributes.get("class");
-                let x1 = attributes.get("x1").unwrap().parse::<f64>().unwrap() / 1000. - 1.;
-                let x2 = attributes.get("x2").unwrap().parse::<f64>().unwrap() / 1000. - 1.;
-                let y1 = attributes.get("y1").unwrap().parse::<f64>().unwrap() / 1000. - 1.;
-                let y2 = attributes.get("y2").unwrap().parse::<f64>().unwrap() / 1000. - 1.;
-
-                let beginning = KleinPoint::new(x1, y1);
-                let end = KleinPoint::new(x2, y2);
-                let wall = KleinWall{
-                    beginning: beginning,
-                    end: end,
-                    texture: match class {
-                        Some(val) => val.to_string(),
-                        None => "WALL".to_string(),
-                    },
-                    height: WALL_HEIGHT as f64,
-                };
-
-                Some(wall)
+/// Id of the layer holding the outline of the disk.
+const BOUNDARY_LAYER: &str = "layer1";
+
+/// Class of the `<circle>` elements which are lights.
+pub const LIGHT_CLASS: &str = "light";
+
+/// Attribute of the root or of a layer declaring the model it is drawn in.
+const MODEL_ATTRIBUTE: &str = "data-model";
+
+/// Largest cosine of the angle between a wall of a Poincaré map
+/// and the boundary, for the wall to be taken as a geodesic.
+const GEODESIC_TOLERANCE: f64 = 0.01;
+
+/// Model of the hyperbolic plane a map is drawn in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Model {
+    /// Geodesics are straight chords of the disk.
+    Klein,
+    /// Geodesics are diameters, or arcs of circles orthogonal to the boundary.
+    Poincare,
+}
+
+impl Model {
+    /// Reads the value of the `data-model` attribute.
+    pub fn parse(value: &str) -> Option<Model> {
+        match value.trim() {
+            "klein" => Some(Model::Klein),
+            "poincare" => Some(Model::Poincare),
+            _ => None,
+        }
+    }
+}
+
+/// A group or the root, as seen by the elements inside it.
+struct Layer {
+    id: Option<String>,
+    transform: Matrix3<f64>,
+    model: Model,
+}
+
+/// A drawable element, along with the transform from its
+/// coordinates to the coordinates of the document.
+struct Shape {
+    element: ElementRef,
+    attributes: Attributes,
+    transform: Matrix3<f64>,
+    model: Model,
+}
+
+/// Where the disk lies in the coordinates of the document.
+struct DiskFrame {
+    center: (f64, f64),
+    radius: f64,
+}
+
+impl DiskFrame {
+    /// Frame of maps which don't say where the disk is:
+    /// a 2000x2000 canvas with the disk touching its edges.
+    const DEFAULT: DiskFrame = DiskFrame {
+        center: (1000., 1000.),
+        radius: 1000.,
+    };
+
+    /// Frame filling a rectangle.
+    fn from_rectangle(x: f64, y: f64, width: f64, height: f64) -> DiskFrame {
+        DiskFrame {
+            center: (x + width / 2., y + height / 2.),
+            radius: width.min(height) / 2.,
+        }
+    }
+
+    /// Converts a point of the shape to the unit disk, in the model
+    /// it is drawn in.
+    fn to_disk(&self, shape: &Shape, x: f64, y: f64) -> (f64, f64) {
+        let (x, y) = svgtransform::apply(&shape.transform, x, y);
+        (
+            (x - self.center.0) / self.radius,
+            (y - self.center.1) / self.radius,
+        )
+    }
+
+    /// Converts a point of the shape to the Klein disk.
+    fn to_klein(&self, shape: &Shape, x: f64, y: f64) -> KleinPoint {
+        let (x, y) = self.to_disk(shape, x, y);
+        match shape.model {
+            Model::Klein => KleinPoint::new(x, y),
+            Model::Poincare => KleinPoint::from(HyperPoint::from(PoincarePoint::new(x, y))),
+        }
+    }
+}
+
+/// Loads a map drawn in the Klein model.
+/// Walls are `<line>`, `<polyline>`, `<polygon>` and `<path>` elements,
+/// textured by their `class`, pickups are `<ellipse>` elements,
+/// and lights are `<circle>` elements of class `light`.
+///
+/// The root or a layer with `data-model="poincare"` is drawn in the
+/// Poincaré model instead: its walls are arcs orthogonal to the boundary
+/// (`A` commands of paths) or straight lines through the center.
+/// Walls which aren't geodesics are rejected.
+///
+/// The disk is the `<circle>` in `layer1` if there is one,
+/// otherwise it is inscribed in the `viewBox`, or in the `width` and `height`.
+/// Transforms of the elements and the groups they are in are applied.
+/// Also returns warnings about the elements which were skipped.
+// Tag names of the svg crate are constants in camel case.
+#[allow(non_upper_case_globals)]
+pub fn load_map_with_warnings(
+    content: &str,
+) -> Result<(HyperMap, Vec<MapLoadWarning>), MapLoadError> {
+    let parser = svg::read(content).map_err(|e| MapLoadError::Syntax(e.to_string()))?;
+
+    let mut shapes = vec![];
+    let mut warnings = vec![];
+    let mut root = None;
+    let mut root_frame = None;
+    let mut boundary = None;
+    // Enclosing groups, and the root.
+    let mut groups: Vec<Layer> = vec![];
+    let mut index = 0;
+
+    // First collect the elements, as the boundary of the disk
+    // may come after the walls in the document.
+    for event in parser {
+        let (tag, kind, attributes) = match event {
+            Event::Error(e) => return Err(MapLoadError::Syntax(e.to_string())),
+            Event::Tag(tag, kind, attributes) => (tag, kind, attributes),
+            _ => continue,
+        };
+        if kind == Type::End {
+            if tag == Group || tag == SVG {
+                groups.pop();
+            }
+            continue;
+        }
+
+        index += 1;
+        let element = ElementRef {
+            tag: tag.to_string(),
+            id: attributes.get("id").map(|id| id.to_string()),
+            index,
+        };
+        let parent_transform = groups.last().map_or(Matrix3::identity(), |g| g.transform);
+        let transform = match attributes.get("transform") {
+            Some(value) => {
+                parent_transform
+                    * svgtransform::parse_transform(value).ok_or_else(|| {
+                        MapLoadError::InvalidAttribute {
+                            element: element.clone(),
+                            attribute: "transform".to_string(),
+                            value: value.to_string(),
+                        }
+                    })?
+            }
+            None => parent_transform,
+        };
+        let model = match attributes.get(MODEL_ATTRIBUTE) {
+            Some(value) => Model::parse(value).ok_or_else(|| MapLoadError::InvalidAttribute {
+                element: element.clone(),
+                attribute: MODEL_ATTRIBUTE.to_string(),
+                value: value.to_string(),
+            })?,
+            None => groups.last().map_or(Model::Klein, |g| g.model),
+        };
+        let shape = Shape {
+            element,
+            attributes,
+            transform,
+            model,
+        };
+        if kind == Type::Start && (tag == Group || tag == SVG) {
+            groups.push(Layer {
+                id: shape.element.id.clone(),
+                transform: shape.transform,
+                model: shape.model,
+            });
+        }
+
+        match tag {
+            SVG if root.is_none() => {
+                root_frame = Some(read_root_frame(&shape.attributes));
+                root = Some(shape);
+            }
+            Line | Path | Polygon | Polyline | Ellipse => shapes.push(shape),
+            Circle if shape.attributes.get("class").is_some_and(|c| c.trim() == LIGHT_CLASS) => {
+                shapes.push(shape)
+            }
+            Circle if boundary.is_none()
+                && groups.iter().any(|g| g.id.as_deref() == Some(BOUNDARY_LAYER)) =>
+            {
+                boundary = Some(read_boundary(&shape)?);
+            }
+            Circle | Rectangle | Text => {
+                warnings.push(MapLoadWarning {
+                    element: shape.element,
+                    message: "unsupported element, skipped".to_string(),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    let frame = match (boundary, root_frame) {
+        (Some(frame), _) => frame,
+        (None, Some(frame)) => frame,
+        (None, None) => return Err(MapLoadError::Syntax("no <svg> element found".to_string())),
+    };
+
+    // Then convert them into the Klein disk.
+    let mut walls = vec![];
+    let mut objects = vec![];
+    let mut lights = vec![];
+    for shape in &shapes {
+        let new_walls = match shape.element.tag.as_str() {
+            Line => vec![read_line(shape, &frame)?],
+            Path => read_path(shape, &frame, &mut warnings)?,
+            Polygon | Polyline => read_points(shape, &frame)?,
+            Circle => {
+                lights.push(read_light(shape, &frame)?);
+                continue;
+            }
+            _ => {
+                objects.push(HyperObject::from(read_ellipse(shape, &frame)?));
+                continue;
+            }
+        };
+        let height = read_size(shape, "data-height", WALL_HEIGHT as f64)?;
+        walls.extend(new_walls.into_iter().map(|mut wall| {
+            wall.height = height;
+            HyperWall::from(wall)
+        }));
+    }
+    let mut map = HyperMap::new_with(walls, objects);
+    let mut info = match &root {
+        Some(root) => read_info(root, &frame)?,
+        None => MapInfo::default(),
+    };
+    info.lights = lights;
+    map.set_info(info);
+    Ok((map, warnings))
+}
+
+/// Reads the metadata given by `data-` attributes of the root: `data-title`,
+/// `data-author`, `data-start` (a point) and `data-heading` (in degrees),
+/// `data-goal` (a completion rule), `data-exit` (a point) and `data-par` (in seconds).
+fn read_info(root: &Shape, frame: &DiskFrame) -> Result<MapInfo, MapLoadError> {
+    let attributes = &root.attributes;
+    let mut info = MapInfo::default();
+    if let Some(title) = attributes.get("data-title") {
+        info.title = title.to_string();
+    }
+    if let Some(author) = attributes.get("data-author") {
+        info.author = author.to_string();
+    }
+    if attributes.contains_key("data-start") {
+        info.start.position = read_point(root, frame, "data-start")?;
+    }
+    if attributes.contains_key("data-heading") {
+        info.start.heading = read_number(root, "data-heading")?.to_radians();
+    }
+    if let Some(value) = attributes.get("data-goal") {
+        info.rule = Some(CompletionRule::parse(value).ok_or_else(|| {
+            MapLoadError::InvalidAttribute {
+                element: root.element.clone(),
+                attribute: "data-goal".to_string(),
+                value: value.to_string(),
+            }
+        })?);
+    }
+    if attributes.contains_key("data-exit") {
+        info.exit = Some(read_point(root, frame, "data-exit")?);
+    } else if info.rule == Some(CompletionRule::ReachExit) {
+        return Err(MapLoadError::MissingAttribute {
+            element: root.element.clone(),
+            attribute: "data-exit".to_string(),
+        });
+    }
+    if attributes.contains_key("data-par") {
+        info.par_time = Some(read_number(root, "data-par")?);
+    }
+    Ok(info)
+}
+
+/// Reads a point written as `x y`, which must lie inside the disk.
+fn read_point(shape: &Shape, frame: &DiskFrame, attribute: &str) -> Result<PoincarePoint, MapLoadError> {
+    let value = &shape.attributes[attribute];
+    match svgtransform::parse_numbers(value).as_deref() {
+        Some(&[x, y]) if x.is_finite() && y.is_finite() => {
+            let point = frame.to_klein(shape, x, y);
+            if point.0.coords.norm() < 1. {
+                return Ok(PoincarePoint::from(HyperPoint::from(point)));
             }
+        }
+        _ => {}
+    }
+    Err(MapLoadError::InvalidAttribute {
+        element: shape.element.clone(),
+        attribute: attribute.to_string(),
+        value: value.to_string(),
+    })
+}
+
+/// Frame given by the root `<svg>` element's `viewBox`,
+/// or its `width` and `height`.
+fn read_root_frame(attributes: &Attributes) -> DiskFrame {
+    let view_box = attributes
+        .get("viewBox")
+        .and_then(|v| svgtransform::parse_numbers(v));
+    if let Some(&[x, y, width, height]) = view_box.as_deref() {
+        if width > 0. && height > 0. {
+            return DiskFrame::from_rectangle(x, y, width, height);
+        }
+    }
+
+    let length = |name: &str| {
+        attributes
+            .get(name)
+            .and_then(|v| v.trim().trim_end_matches("px").parse::<f64>().ok())
+            .filter(|v| *v > 0.)
+    };
+    match (length("width"), length("height")) {
+        (Some(width), Some(height)) => DiskFrame::from_rectangle(0., 0., width, height),
+        _ => DiskFrame::DEFAULT,
+    }
+}
+
+/// Frame given by the `<circle>` outlining the disk.
+fn read_boundary(shape: &Shape) -> Result<DiskFrame, MapLoadError> {
+    let cx = read_number(shape, "cx")?;
+    let cy = read_number(shape, "cy")?;
+    let r = read_number(shape, "r")?;
+    let radius = r * svgtransform::scale_factor(&shape.transform);
+    if radius <= 0. {
+        return Err(MapLoadError::InvalidAttribute {
+            element: shape.element.clone(),
+            attribute: "r".to_string(),
+            value: r.to_string(),
+        });
+    }
+
+    Ok(DiskFrame {
+        center: svgtransform::apply(&shape.transform, cx, cy),
+        radius,
+    })
+}
+
+/// Reads a wall from a `<line>` element.
+fn read_line(shape: &Shape, frame: &DiskFrame) -> Result<KleinWall, MapLoadError> {
+    let x1 = read_number(shape, "x1")?;
+    let y1 = read_number(shape, "y1")?;
+    let x2 = read_number(shape, "x2")?;
+    let y2 = read_number(shape, "y2")?;
+
+    make_wall(shape, frame, (x1, y1), (x2, y2))
+}
 
-            _ => None
+/// Reads one wall per segment of a `<polyline>`, or of a `<polygon>`
+/// which is closed by a wall from its last point back to its first.
+fn read_points(shape: &Shape, frame: &DiskFrame) -> Result<Vec<KleinWall>, MapLoadError> {
+    let value = shape
+        .attributes
+        .get("points")
+        .ok_or_else(|| MapLoadError::MissingAttribute {
+            element: shape.element.clone(),
+            attribute: "points".to_string(),
+        })?;
+    let mut points = match svgtransform::parse_numbers(value) {
+        Some(numbers) if numbers.len() % 2 == 0 && numbers.iter().all(|n| n.is_finite()) => {
+            numbers.chunks(2).map(|p| (p[0], p[1])).collect::<Vec<_>>()
+        }
+        _ => {
+            return Err(MapLoadError::InvalidAttribute {
+                element: shape.element.clone(),
+                attribute: "points".to_string(),
+                value: value.to_string(),
+            })
         }
-    }).map(|w| HyperWall::from(w)).collect();
+    };
+    if shape.element.tag == Polygon && !points.is_empty() {
+        points.push(points[0]);
+    }
 
-    let objects: Vec<_> = svg::read(content).unwrap().filter_map(|event| {
-        match event {
-            Event::Tag(Ellipse, _, attributes) => {
-                let id = attributes.get("id").unwrap();
-                let stroke = attributes.get("stroke").unwrap();
-                let x = attributes.get("cx").unwrap().parse::<f64>().unwrap() / 1000. - 1.;
-                let y = attributes.get("cy").unwrap().parse::<f64>().unwrap() / 1000. - 1.;
+    make_walls(shape, frame, &points)
+}
+
+/// Reads one wall per straight segment of a `<path>`, and in the Poincaré
+/// model one per arc. Subpaths ended by `Z` are closed.
+/// Other curves are skipped with a warning.
+fn read_path(
+    shape: &Shape,
+    frame: &DiskFrame,
+    warnings: &mut Vec<MapLoadWarning>,
+) -> Result<Vec<KleinWall>, MapLoadError> {
+    let value = shape
+        .attributes
+        .get("d")
+        .ok_or_else(|| MapLoadError::MissingAttribute {
+            element: shape.element.clone(),
+            attribute: "d".to_string(),
+        })?;
+    let invalid = || MapLoadError::InvalidAttribute {
+        element: shape.element.clone(),
+        attribute: "d".to_string(),
+        value: value.to_string(),
+    };
+    let data = Data::parse(value).map_err(|_| invalid())?;
 
-                let position = KleinPoint::new(x, y);
-                let object = KleinObject{
-                    position: position,
-                    active: true
-                };
+    let mut walls = vec![];
+    // Points of the current subpath.
+    let mut points = vec![];
+    let mut start = (0., 0.);
+    let mut current = (0., 0.);
+    let mut curved = false;
+    for command in data.iter() {
+        let (position, parameters) = match command {
+            Command::Close => {
+                points.push(start);
+                walls.extend(make_walls(shape, frame, &points)?);
+                current = start;
+                points = vec![start];
+                continue;
+            }
+            Command::Move(position, parameters)
+            | Command::Line(position, parameters)
+            | Command::HorizontalLine(position, parameters)
+            | Command::VerticalLine(position, parameters)
+            | Command::QuadraticCurve(position, parameters)
+            | Command::SmoothQuadraticCurve(position, parameters)
+            | Command::CubicCurve(position, parameters)
+            | Command::SmoothCubicCurve(position, parameters)
+            | Command::EllipticalArc(position, parameters) => (position, parameters),
+        };
+        let numbers: Vec<f64> = parameters.iter().map(|&n| n as f64).collect();
+        // Number of parameters taken by each repetition of the command.
+        let arity = match command {
+            Command::HorizontalLine(..) | Command::VerticalLine(..) => 1,
+            Command::Move(..) | Command::Line(..) | Command::SmoothQuadraticCurve(..) => 2,
+            Command::QuadraticCurve(..) | Command::SmoothCubicCurve(..) => 4,
+            Command::CubicCurve(..) => 6,
+            _ => 7,
+        };
+        if numbers.is_empty() || !numbers.len().is_multiple_of(arity) {
+            return Err(invalid());
+        }
 
-                Some(object)
+        for (i, args) in numbers.chunks(arity).enumerate() {
+            let origin = match position {
+                Position::Absolute => (0., 0.),
+                Position::Relative => current,
+            };
+            let next = match command {
+                Command::HorizontalLine(..) => (origin.0 + args[0], current.1),
+                Command::VerticalLine(..) => (current.0, origin.1 + args[0]),
+                _ => (origin.0 + args[arity - 2], origin.1 + args[arity - 1]),
+            };
+            match command {
+                // Pairs after the first one of a move are lines.
+                Command::Move(..) if i == 0 => {
+                    walls.extend(make_walls(shape, frame, &points)?);
+                    start = next;
+                    points = vec![next];
+                }
+                Command::Move(..)
+                | Command::Line(..)
+                | Command::HorizontalLine(..)
+                | Command::VerticalLine(..) => points.push(next),
+                Command::EllipticalArc(..) if shape.model == Model::Poincare => {
+                    walls.extend(make_walls(shape, frame, &points)?);
+                    if next != current {
+                        walls.push(read_arc(shape, frame, current, next, args)?);
+                    }
+                    points = vec![next];
+                }
+                _ => {
+                    // The curve breaks the chain of walls.
+                    curved = true;
+                    walls.extend(make_walls(shape, frame, &points)?);
+                    points = vec![next];
+                }
             }
-            _ => None
+            current = next;
         }
-    }).map(|o| HyperObject::from(o)).collect();
+    }
+    walls.extend(make_walls(shape, frame, &points)?);
 
-    let map = HyperMap::new_with(walls, objects);
-    map
+    if curved {
+        warnings.push(MapLoadWarning {
+            element: shape.element.clone(),
+            message: "curved segments skipped".to_string(),
+        });
+    }
+    Ok(walls)
+}
+
+/// Wall along an arc of a Poincaré map, from `from` to `to`.
+/// `args` are the parameters of the `A` command.
+fn read_arc(
+    shape: &Shape,
+    frame: &DiskFrame,
+    from: (f64, f64),
+    to: (f64, f64),
+    args: &[f64],
+) -> Result<KleinWall, MapLoadError> {
+    let not_geodesic = || MapLoadError::NotGeodesic {
+        element: shape.element.clone(),
+    };
+    let (rx, ry) = (args[0].abs(), args[1].abs());
+    let (large_arc, sweep) = (args[3] != 0., args[4] != 0.);
+    if (rx - ry).abs() > GEODESIC_TOLERANCE * rx.max(ry) {
+        return Err(not_geodesic());
+    }
+
+    // Find the center of the arc in the disk, as SVG renderers do.
+    let a = frame.to_disk(shape, from.0, from.1);
+    let b = frame.to_disk(shape, to.0, to.1);
+    let half = ((a.0 - b.0) / 2., (a.1 - b.1) / 2.);
+    let half_squared = half.0 * half.0 + half.1 * half.1;
+    // Radii too small to reach are scaled up.
+    let radius = (rx * svgtransform::scale_factor(&shape.transform) / frame.radius)
+        .max(half_squared.sqrt());
+    // Mirroring transforms reverse the direction of the sweep.
+    let sweep = sweep != (svgtransform::determinant(&shape.transform) < 0.);
+    let offset = ((radius * radius - half_squared) / half_squared).sqrt();
+    let offset = if large_arc != sweep { offset } else { -offset };
+    let center = (
+        (a.0 + b.0) / 2. + offset * half.1,
+        (a.1 + b.1) / 2. - offset * half.0,
+    );
+
+    // Geodesics are the short arcs of circles orthogonal to the boundary.
+    let cosine = (center.0 * center.0 + center.1 * center.1 - radius * radius - 1.) / (2. * radius);
+    if large_arc || cosine.abs() > GEODESIC_TOLERANCE {
+        return Err(not_geodesic());
+    }
+    Ok(new_wall(shape, frame, from, to))
+}
+
+/// Walls joining consecutive points, skipping those of zero length.
+fn make_walls(
+    shape: &Shape,
+    frame: &DiskFrame,
+    points: &[(f64, f64)],
+) -> Result<Vec<KleinWall>, MapLoadError> {
+    points
+        .windows(2)
+        .filter(|p| p[0] != p[1])
+        .map(|p| make_wall(shape, frame, p[0], p[1]))
+        .collect()
+}
+
+/// Straight wall between two points of the shape. In the Poincaré model,
+/// only those through the center of the disk are geodesics.
+fn make_wall(
+    shape: &Shape,
+    frame: &DiskFrame,
+    a: (f64, f64),
+    b: (f64, f64),
+) -> Result<KleinWall, MapLoadError> {
+    if shape.model == Model::Poincare {
+        let (a, b) = (frame.to_disk(shape, a.0, a.1), frame.to_disk(shape, b.0, b.1));
+        // Distance from the center to the line.
+        let distance = (a.0 * b.1 - a.1 * b.0).abs() / (a.0 - b.0).hypot(a.1 - b.1);
+        if distance > GEODESIC_TOLERANCE {
+            return Err(MapLoadError::NotGeodesic {
+                element: shape.element.clone(),
+            });
+        }
+    }
+    Ok(new_wall(shape, frame, a, b))
+}
+
+/// Wall between two points of the shape, textured by its `class`.
+fn new_wall(shape: &Shape, frame: &DiskFrame, a: (f64, f64), b: (f64, f64)) -> KleinWall {
+    KleinWall {
+        beginning: frame.to_klein(shape, a.0, a.1),
+        end: frame.to_klein(shape, b.0, b.1),
+        texture: match shape.attributes.get("class") {
+            Some(val) => val.to_string(),
+            None => "WALL".to_string(),
+        },
+        height: WALL_HEIGHT as f64,
+    }
+}
+
+/// Reads a pickup from an `<ellipse>` element. Its radius is `rx`,
+/// relative to the radius of the disk.
+fn read_ellipse(shape: &Shape, frame: &DiskFrame) -> Result<KleinObject, MapLoadError> {
+    let x = read_number(shape, "cx")?;
+    let y = read_number(shape, "cy")?;
+    let scale = svgtransform::scale_factor(&shape.transform) / frame.radius;
+    let size = read_size(shape, "rx", default_object_size() / scale)? * scale;
+
+    Ok(KleinObject {
+        position: frame.to_klein(shape, x, y),
+        active: true,
+        size,
+    })
+}
+
+/// Reads a light from a `<circle>` of class `light`. Its `fill`, written
+/// as `#rrggbb`, is the color of the light, and `data-intensity` its brightness.
+fn read_light(shape: &Shape, frame: &DiskFrame) -> Result<Light, MapLoadError> {
+    let x = read_number(shape, "cx")?;
+    let y = read_number(shape, "cy")?;
+    let position = frame.to_klein(shape, x, y);
+    if position.0.coords.norm() >= 1. {
+        return Err(MapLoadError::InvalidAttribute {
+            element: shape.element.clone(),
+            attribute: "cx".to_string(),
+            value: x.to_string(),
+        });
+    }
+    let color = match shape.attributes.get("fill") {
+        Some(value) => read_color(value).ok_or_else(|| MapLoadError::InvalidAttribute {
+            element: shape.element.clone(),
+            attribute: "fill".to_string(),
+            value: value.to_string(),
+        })?,
+        None => default_light_color(),
+    };
+
+    Ok(Light {
+        position: PoincarePoint::from(HyperPoint::from(position)),
+        intensity: read_size(shape, "data-intensity", default_intensity())?,
+        color,
+    })
+}
+
+/// Reads a color written as `#rrggbb`.
+fn read_color(value: &str) -> Option<[u8; 3]> {
+    let hex = value.trim().strip_prefix('#').filter(|hex| hex.len() == 6)?;
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// Reads an optional positive attribute of an element.
+fn read_size(shape: &Shape, attribute: &str, default: f64) -> Result<f64, MapLoadError> {
+    if !shape.attributes.contains_key(attribute) {
+        return Ok(default);
+    }
+    match read_number(shape, attribute)? {
+        size if size > 0. => Ok(size),
+        _ => Err(MapLoadError::InvalidAttribute {
+            element: shape.element.clone(),
+            attribute: attribute.to_string(),
+            value: shape.attributes[attribute].to_string(),
+        }),
+    }
+}
+
+/// Reads a numeric attribute of an element.
+fn read_number(shape: &Shape, attribute: &str) -> Result<f64, MapLoadError> {
+    let value = shape
+        .attributes
+        .get(attribute)
+        .ok_or_else(|| MapLoadError::MissingAttribute {
+            element: shape.element.clone(),
+            attribute: attribute.to_string(),
+        })?;
+    match value.trim().parse::<f64>() {
+        Ok(number) if number.is_finite() => Ok(number),
+        _ => Err(MapLoadError::InvalidAttribute {
+            element: shape.element.clone(),
+            attribute: attribute.to_string(),
+            value: value.to_string(),
+        }),
+    }
+}
+
+/// Loads a map like `load_map_with_warnings`, without the warnings.
+#[cfg(test)]
+pub fn load_map(content: &str) -> Result<HyperMap, MapLoadError> {
+    load_map_with_warnings(content).map(|(map, _)| map)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    fn svg(body: &str) -> String {
+        format!(
+            "<svg width=\"2000\" height=\"2000\" xmlns=\"http://www.w3.org/2000/svg\">{}</svg>",
+            body
+        )
+    }
+
+    #[test]
+    fn test_not_svg() {
+        assert!(matches!(load_map("fsdfds"), Err(MapLoadError::Syntax(_))));
+        assert!(matches!(load_map(""), Err(MapLoadError::Syntax(_))));
+    }
+
     #[test]
-    fn test() {
-        load_map("fsdfds");
-        assert!(true);
+    fn test_malformed_svg() {
+        let result = load_map("<svg><line x1=\"1000\" <!-- </svg>");
+        assert!(matches!(result, Err(MapLoadError::Syntax(_))));
+    }
+
+    #[test]
+    fn test_missing_attribute() {
+        let result = load_map(&svg("<line id=\"svg_3\" x1=\"1000\" y1=\"1000\" x2=\"1200\"/>"));
+        match result {
+            Err(MapLoadError::MissingAttribute { element, attribute }) => {
+                assert_eq!(element.tag, "line");
+                assert_eq!(element.id.as_deref(), Some("svg_3"));
+                assert_eq!(element.index, 2);
+                assert_eq!(attribute, "y2");
+            }
+            _ => panic!("expected a missing attribute error"),
+        }
+    }
+
+    #[test]
+    fn test_invalid_attribute() {
+        let result = load_map(&svg("<ellipse cx=\"abc\" cy=\"1000\"/>"));
+        match result {
+            Err(MapLoadError::InvalidAttribute {
+                element,
+                attribute,
+                value,
+            }) => {
+                assert_eq!(element.id, None);
+                assert_eq!(attribute, "cx");
+                assert_eq!(value, "abc");
+            }
+            _ => panic!("expected an invalid attribute error"),
+        }
+    }
+
+    #[test]
+    fn test_optional_attributes() {
+        let map = load_map(&svg(
+            "<line x1=\"1000\" y1=\"1000\" x2=\"1200\" y2=\"1000\"/><ellipse cx=\"900\" cy=\"900\"/>",
+        ))
+        .unwrap();
+        let wall = map.get_walls_iter().next().unwrap();
+        assert_eq!(wall.texture, "WALL");
+        assert_eq!(wall.height, WALL_HEIGHT as f64);
+        assert_eq!(map.get_objects_iter().count(), 1);
+        assert_eq!(map.get_objects_iter().next().unwrap().size, OBJECT_RADIUS as f64);
+    }
+
+    #[test]
+    fn test_sizes() {
+        let map = load_map(&svg(
+            "<g transform=\"scale(2)\"><line x1=\"500\" y1=\"500\" x2=\"600\" y2=\"500\" data-height=\"0.3\"/>\
+             <ellipse cx=\"450\" cy=\"450\" rx=\"25\" ry=\"25\"/></g>",
+        ))
+        .unwrap();
+        assert_eq!(map.get_walls_iter().next().unwrap().height, 0.3);
+        let size = map.get_objects_iter().next().unwrap().size;
+        assert!((size - 0.05).abs() < 1e-9);
+
+        let result = load_map(&svg("<line x1=\"1000\" y1=\"1000\" x2=\"1200\" y2=\"1000\" data-height=\"0\"/>"));
+        assert!(matches!(result, Err(MapLoadError::InvalidAttribute { .. })));
+    }
+
+    #[test]
+    fn test_skipped_elements() {
+        let (map, warnings) = load_map_with_warnings(&svg(
+            "<g id=\"layer1\"><circle cx=\"1000\" cy=\"1000\" r=\"1000\"/></g>\
+             <rect id=\"room\" x=\"0\" y=\"0\" width=\"10\" height=\"10\"/>",
+        ))
+        .unwrap();
+        assert_eq!(map.get_walls_iter().count(), 0);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].element.id.as_deref(), Some("room"));
+    }
+
+    fn assert_klein(point: &KleinPoint, x: f64, y: f64) {
+        assert!(
+            (point.0.x - x).abs() < 1e-9 && (point.0.y - y).abs() < 1e-9,
+            "{:?} != ({}, {})",
+            point,
+            x,
+            y
+        );
+    }
+
+    fn first_wall(map: &HyperMap) -> KleinWall {
+        KleinWall::from(map.get_walls_iter().next().unwrap().clone())
+    }
+
+    #[test]
+    fn test_view_box() {
+        let map = load_map(
+            "<svg viewBox=\"-100 0 200 200\"><line x1=\"0\" y1=\"100\" x2=\"50\" y2=\"150\"/></svg>",
+        )
+        .unwrap();
+        let wall = first_wall(&map);
+        assert_klein(&wall.beginning, 0., 0.);
+        assert_klein(&wall.end, 0.5, 0.5);
+    }
+
+    #[test]
+    fn test_width_height() {
+        let map = load_map(
+            "<svg width=\"400px\" height=\"400\"><line x1=\"200\" y1=\"200\" x2=\"300\" y2=\"200\"/></svg>",
+        )
+        .unwrap();
+        let wall = first_wall(&map);
+        assert_klein(&wall.beginning, 0., 0.);
+        assert_klein(&wall.end, 0.5, 0.);
+    }
+
+    #[test]
+    fn test_boundary_circle() {
+        // The boundary wins over the viewBox, even when it comes last.
+        let map = load_map(
+            "<svg viewBox=\"0 0 2000 2000\">\
+             <line x1=\"500\" y1=\"500\" x2=\"500\" y2=\"300\"/>\
+             <g id=\"layer1\" transform=\"translate(100 100)\">\
+             <circle cx=\"400\" cy=\"400\" r=\"400\"/></g></svg>",
+        )
+        .unwrap();
+        let wall = first_wall(&map);
+        assert_klein(&wall.beginning, 0., 0.);
+        assert_klein(&wall.end, 0., -0.5);
+    }
+
+    #[test]
+    fn test_nested_transforms() {
+        let map = load_map(&svg(
+            "<g transform=\"translate(1000 1000)\"><g transform=\"scale(2)\">\
+             <line transform=\"rotate(90)\" x1=\"0\" y1=\"0\" x2=\"250\" y2=\"0\"/>\
+             <ellipse cx=\"-250\" cy=\"0\" rx=\"5\" ry=\"5\"/></g></g>",
+        ))
+        .unwrap();
+        let wall = first_wall(&map);
+        assert_klein(&wall.beginning, 0., 0.);
+        assert_klein(&wall.end, 0., 0.5);
+        let object = KleinObject::from(map.get_objects_iter().next().unwrap());
+        assert_klein(&object.position, -0.5, 0.);
+    }
+
+    #[test]
+    fn test_invalid_transform() {
+        let result = load_map(&svg("<g id=\"rooms\" transform=\"spin(3)\"></g>"));
+        assert!(matches!(result, Err(MapLoadError::InvalidAttribute { .. })));
+    }
+
+    #[test]
+    fn test_polygon() {
+        let map = load_map(&svg(
+            "<polygon class=\"BRICK\" points=\"1000,1000 1500,1000 1500,1500\"/>\
+             <polyline points=\"0 0, 10 0, 10 0, 10 10\"/>",
+        ))
+        .unwrap();
+        let walls: Vec<KleinWall> = map
+            .get_walls_iter()
+            .map(|w| KleinWall::from(w.clone()))
+            .collect();
+        // The polygon is closed, the repeated point of the polyline skipped.
+        assert_eq!(walls.len(), 5);
+        assert!(walls[..3].iter().all(|w| w.texture == "BRICK"));
+        assert_klein(&walls[2].beginning, 0.5, 0.5);
+        assert_klein(&walls[2].end, 0., 0.);
+
+        let result = load_map(&svg("<polyline points=\"0 0 10\"/>"));
+        assert!(matches!(result, Err(MapLoadError::InvalidAttribute { .. })));
+    }
+
+    #[test]
+    fn test_path() {
+        let (map, warnings) = load_map_with_warnings(&svg(
+            "<path d=\"M 1000 1000 h 500 v 500 Z m 0 -250 l 100 0 100 0 \
+             q 50 50 100 0 L 1000 0\"/>",
+        ))
+        .unwrap();
+        let walls: Vec<KleinWall> = map
+            .get_walls_iter()
+            .map(|w| KleinWall::from(w.clone()))
+            .collect();
+        assert_eq!(walls.len(), 6);
+        assert_klein(&walls[1].end, 0.5, 0.5);
+        assert_klein(&walls[2].end, 0., 0.);
+        // The relative move starts from the start of the closed subpath.
+        assert_klein(&walls[3].beginning, 0., -0.25);
+        assert_klein(&walls[4].end, 0.2, -0.25);
+        assert_klein(&walls[5].beginning, 0.3, -0.25);
+        assert_eq!(warnings.len(), 1);
+
+        let result = load_map(&svg("<path d=\"M 0 0 L 10\"/>"));
+        assert!(matches!(result, Err(MapLoadError::InvalidAttribute { .. })));
+    }
+
+    #[test]
+    fn test_poincare_model() {
+        // A geodesic arc between (0.6, 0) and (0, 0.6), on the circle
+        // orthogonal to the boundary with its center at (17/15, 17/15).
+        let radius = (2. * (17f64 / 15.).powi(2) - 1.).sqrt() * 1000.;
+        let arc = format!(
+            "<path d=\"M 1600 1000 A {r} {r} 0 0 0 1000 1600\"/>",
+            r = radius
+        );
+        let map = load_map(&svg(&format!(
+            "<g data-model=\"poincare\">{}<line x1=\"1000\" y1=\"1000\" x2=\"1600\" y2=\"1000\"/></g>",
+            arc
+        )))
+        .unwrap();
+        let walls: Vec<KleinWall> = map
+            .get_walls_iter()
+            .map(|w| KleinWall::from(w.clone()))
+            .collect();
+        // 0.6 in the Poincaré disk is 2 * 0.6 / (1 + 0.36) in the Klein disk.
+        let k = 1.2 / 1.36;
+        assert_klein(&walls[0].beginning, k, 0.);
+        assert_klein(&walls[0].end, 0., k);
+        assert_klein(&walls[1].end, k, 0.);
+
+        // The same arc, bulging the other way.
+        let result = load_map(&svg(&format!(
+            "<g data-model=\"poincare\">{}</g>",
+            arc.replace("0 0 0", "0 0 1")
+        )));
+        assert!(matches!(result, Err(MapLoadError::NotGeodesic { .. })));
+
+        // Straight lines away from the center aren't geodesics.
+        let result = load_map(
+            "<svg width=\"2000\" height=\"2000\" data-model=\"poincare\">\
+             <line x1=\"1000\" y1=\"1500\" x2=\"1500\" y2=\"1500\"/></svg>",
+        );
+        assert!(matches!(result, Err(MapLoadError::NotGeodesic { .. })));
+
+        let result = load_map(&svg("<g data-model=\"euclid\"></g>"));
+        assert!(matches!(result, Err(MapLoadError::InvalidAttribute { .. })));
+    }
+
+    #[test]
+    fn test_metadata() {
+        let map = load_map(
+            "<svg width=\"2000\" height=\"2000\" data-title=\"Exit\" data-start=\"1500 1000\" \
+             data-heading=\"90\" data-goal=\"reach-exit\" data-exit=\"1000 500\" data-par=\"30\"/>",
+        )
+        .unwrap();
+        let info = map.get_info();
+        assert_eq!(info.title, "Exit");
+        assert_eq!(info.rule, Some(CompletionRule::ReachExit));
+        assert_eq!(info.par_time, Some(30.));
+        assert!((info.start.heading - std::f64::consts::FRAC_PI_2).abs() < 1e-9);
+        // 0.5 in the Klein disk is 0.5 / (1 + sqrt(0.75)) in the Poincaré disk.
+        let poincare = 0.5 / (1. + 0.75f64.sqrt());
+        assert!((info.start.position.0.x - poincare).abs() < 1e-9);
+        assert!((info.exit.as_ref().unwrap().0.y + poincare).abs() < 1e-9);
+
+        let map = load_map(&svg(
+            "<circle class=\"light\" cx=\"1000\" cy=\"1500\" r=\"20\" fill=\"#ff8000\" data-intensity=\"2\"/>\
+             <circle class=\"light\" cx=\"500\" cy=\"1000\" r=\"20\"/>",
+        ))
+        .unwrap();
+        let lights = &map.get_info().lights;
+        assert_eq!(lights.len(), 2);
+        assert!((lights[0].position.0.y - poincare).abs() < 1e-9);
+        assert_eq!(lights[0].intensity, 2.);
+        assert_eq!(lights[0].color, [255, 128, 0]);
+        assert_eq!(lights[1].color, [255, 255, 255]);
+        let result = load_map(&svg("<circle class=\"light\" cx=\"1000\" cy=\"1000\" r=\"20\" fill=\"orange\"/>"));
+        assert!(matches!(result, Err(MapLoadError::InvalidAttribute { .. })));
+
+        let result = load_map("<svg width=\"2000\" height=\"2000\" data-goal=\"reach-exit\"/>");
+        assert!(matches!(result, Err(MapLoadError::MissingAttribute { .. })));
+        let result = load_map("<svg width=\"2000\" height=\"2000\" data-start=\"3000 0\"/>");
+        assert!(matches!(result, Err(MapLoadError::InvalidAttribute { .. })));
+    }
+
+    #[test]
+    fn test_bundled_maps() {
+        for content in &[
+            include_str!("../../assets/map.svg"),
+            include_str!("../../assets/map2.svg"),
+            include_str!("../../assets/map3.svg"),
+        ] {
+            let (_, warnings) = load_map_with_warnings(content).unwrap();
+            assert!(warnings.is_empty());
+        }
     }
 }
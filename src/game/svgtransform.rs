@@ -0,0 +1,106 @@
+use nalgebra::{Matrix3, Vector3};
+
+/// Parses the value of an SVG `transform` attribute into an affine matrix
+/// in homogeneous coordinates. Returns None if it can't be understood.
+pub fn parse_transform(value: &str) -> Option<Matrix3<f64>> {
+    let mut result = Matrix3::identity();
+    let mut rest = value.trim();
+    while !rest.is_empty() {
+        let open = rest.find('(')?;
+        let close = rest.find(')')?;
+        if close < open {
+            return None;
+        }
+        let name = rest[..open].trim();
+        let args = parse_numbers(&rest[open + 1..close])?;
+
+        let transform = match (name, args.as_slice()) {
+            ("matrix", &[a, b, c, d, e, f]) => Matrix3::new(a, c, e, b, d, f, 0., 0., 1.),
+            ("translate", &[tx]) => translation(tx, 0.),
+            ("translate", &[tx, ty]) => translation(tx, ty),
+            ("scale", &[s]) => scaling(s, s),
+            ("scale", &[sx, sy]) => scaling(sx, sy),
+            ("rotate", &[angle]) => rotation(angle),
+            ("rotate", &[angle, cx, cy]) => {
+                translation(cx, cy) * rotation(angle) * translation(-cx, -cy)
+            }
+            ("skewX", &[angle]) => {
+                Matrix3::new(1., angle.to_radians().tan(), 0., 0., 1., 0., 0., 0., 1.)
+            }
+            ("skewY", &[angle]) => {
+                Matrix3::new(1., 0., 0., angle.to_radians().tan(), 1., 0., 0., 0., 1.)
+            }
+            _ => return None,
+        };
+        result *= transform;
+
+        rest = rest[close + 1..].trim_start();
+        rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
+    }
+    Some(result)
+}
+
+/// Parses a list of numbers separated by commas and/or whitespace.
+pub fn parse_numbers(list: &str) -> Option<Vec<f64>> {
+    list.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse().ok())
+        .collect()
+}
+
+/// Applies an affine transform to a point.
+pub fn apply(transform: &Matrix3<f64>, x: f64, y: f64) -> (f64, f64) {
+    let p = transform * Vector3::new(x, y, 1.);
+    (p.x, p.y)
+}
+
+/// Factor by which the transform scales lengths, on average.
+pub fn scale_factor(transform: &Matrix3<f64>) -> f64 {
+    determinant(transform).abs().sqrt()
+}
+
+/// Determinant of the linear part of the transform,
+/// negative when it mirrors.
+pub fn determinant(transform: &Matrix3<f64>) -> f64 {
+    transform[(0, 0)] * transform[(1, 1)] - transform[(0, 1)] * transform[(1, 0)]
+}
+
+fn translation(x: f64, y: f64) -> Matrix3<f64> {
+    Matrix3::new(1., 0., x, 0., 1., y, 0., 0., 1.)
+}
+
+fn scaling(x: f64, y: f64) -> Matrix3<f64> {
+    Matrix3::new(x, 0., 0., 0., y, 0., 0., 0., 1.)
+}
+
+/// Rotation by the given angle in degrees, clockwise on screen.
+fn rotation(angle: f64) -> Matrix3<f64> {
+    let (sin, cos) = angle.to_radians().sin_cos();
+    Matrix3::new(cos, -sin, 0., sin, cos, 0., 0., 0., 1.)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: (f64, f64), b: (f64, f64)) {
+        assert!((a.0 - b.0).abs() < 1e-9 && (a.1 - b.1).abs() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_parse_transform() {
+        let t = parse_transform("translate(10, 20) scale(2)").unwrap();
+        assert_close(apply(&t, 1., 1.), (12., 22.));
+
+        let t = parse_transform("rotate(90 10 10)").unwrap();
+        assert_close(apply(&t, 20., 10.), (10., 20.));
+
+        let t = parse_transform("matrix(1 0 0 1 5 -5),skewX(45)").unwrap();
+        assert_close(apply(&t, 0., 1.), (6., -4.));
+
+        assert!(parse_transform("").unwrap().is_identity(0.));
+        assert!(parse_transform("translate(1,2").is_none());
+        assert!(parse_transform("spin(3)").is_none());
+        assert!(parse_transform("scale(1, 2, 3)").is_none());
+    }
+}
@@ -0,0 +1,307 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use nalgebra::Matrix3;
+use svg::node::element::path::Data;
+use svg::node::element;
+use svg::Document;
+
+use super::HyperMap;
+use super::mapfile::StartPose;
+use super::svgloader::{Model, LIGHT_CLASS};
+use crate::constants::*;
+use crate::utils::hyperpoint::*;
+use crate::utils::kleinpoint::*;
+use crate::utils::poincarepoint::*;
+
+/// Side of the exported canvas, which the disk touches.
+const CANVAS_SIZE: f64 = 2000.;
+
+/// Radius of the circles marking lights. It has no effect on the light.
+const LIGHT_MARKER_RADIUS: f64 = 15.;
+
+/// Walls closer than this to the center of the Poincaré disk,
+/// relative to its radius, are written as straight lines.
+const STRAIGHT_TOLERANCE: f64 = 1e-3;
+
+/// Writes a map as an SVG document which `svgloader::load_map_with_warnings` reads back.
+///
+/// # Parameters:
+///		- `model`:		Klein gives straight walls, Poincaré geodesic arcs.
+///		- `isometry`:	Applied to the map first, e.g. to centre it on a point.
+pub fn write_map(map: &HyperMap, model: Model, isometry: &Matrix3<f64>) -> String {
+    let radius = CANVAS_SIZE / 2.;
+    let boundary = element::Circle::new()
+        .set("cx", radius)
+        .set("cy", radius)
+        .set("r", radius)
+        .set("fill", "none")
+        .set("stroke", "black");
+
+    let mut walls = element::Group::new().set("id", "walls");
+    for wall in map.get_walls_iter() {
+        let mut beginning = wall.beginning;
+        let mut end = wall.end;
+        beginning.transform(isometry);
+        end.transform(isometry);
+        walls = match model {
+            Model::Klein => walls.add(
+                line(to_canvas(klein(beginning)), to_canvas(klein(end)), &wall.texture)
+                    .set("data-height", wall.height),
+            ),
+            Model::Poincare => add_geodesic(walls, poincare(beginning), poincare(end), wall),
+        };
+    }
+
+    let mut objects = element::Group::new().set("id", "objects");
+    for object in map.get_objects_iter() {
+        let mut position = object.position;
+        position.transform(isometry);
+        let (x, y) = match model {
+            Model::Klein => to_canvas(klein(position)),
+            Model::Poincare => to_canvas(poincare(position)),
+        };
+        let radius = object.size * CANVAS_SIZE / 2.;
+        objects = objects.add(
+            element::Ellipse::new()
+                .set("cx", x)
+                .set("cy", y)
+                .set("rx", radius)
+                .set("ry", radius)
+                .set("fill", "gold"),
+        );
+    }
+
+    let mut document = Document::new()
+        .set("width", CANVAS_SIZE)
+        .set("height", CANVAS_SIZE)
+        .set("viewBox", (0, 0, CANVAS_SIZE, CANVAS_SIZE));
+    if model == Model::Poincare {
+        document = document.set("data-model", "poincare");
+    }
+
+    // Metadata is in world coordinates, while the walls are in the view.
+    let info = map.get_info();
+    let to_export = isometry * map.get_transform();
+    let export_point = |point: &PoincarePoint| {
+        let mut point = HyperPoint::from(point.clone());
+        point.transform(&to_export);
+        match model {
+            Model::Klein => to_canvas(klein(point)),
+            Model::Poincare => to_canvas(poincare(point)),
+        }
+    };
+    if !info.title.is_empty() {
+        document = document.set("data-title", info.title.as_str());
+    }
+    if !info.author.is_empty() {
+        document = document.set("data-author", info.author.as_str());
+    }
+    let (start, heading) = export_pose(&info.start, &to_export);
+    let start = match model {
+        Model::Klein => export_point(&info.start.position),
+        Model::Poincare => to_canvas(start),
+    };
+    document = document
+        .set("data-start", format!("{} {}", start.0, start.1))
+        .set("data-heading", heading.to_degrees());
+    if let Some(rule) = &info.rule {
+        document = document.set("data-goal", rule.to_string());
+    }
+    if let Some(exit) = &info.exit {
+        let exit = export_point(exit);
+        document = document.set("data-exit", format!("{} {}", exit.0, exit.1));
+    }
+    if let Some(par_time) = info.par_time {
+        document = document.set("data-par", par_time);
+    }
+    let mut lights = element::Group::new().set("id", "lights");
+    for light in &info.lights {
+        let (x, y) = export_point(&light.position);
+        let [r, g, b] = light.color;
+        lights = lights.add(
+            element::Circle::new()
+                .set("class", LIGHT_CLASS)
+                .set("cx", x)
+                .set("cy", y)
+                .set("r", LIGHT_MARKER_RADIUS)
+                .set("fill", format!("#{:02x}{:02x}{:02x}", r, g, b))
+                .set("data-intensity", light.intensity),
+        );
+    }
+    document
+        .add(element::Group::new().set("id", "layer1").add(boundary))
+        .add(walls)
+        .add(objects)
+        .add(lights)
+        .to_string()
+}
+
+/// Writes the map in world coordinates to `EXPORT_DIR`,
+/// named after the file it was loaded from. Returns the path written.
+pub fn export(map: &HyperMap, map_name: &str, model: Model) -> Result<PathBuf, String> {
+    let stem = Path::new(map_name)
+        .file_stem()
+        .map_or("map".to_string(), |s| s.to_string_lossy().to_string());
+    let suffix = match model {
+        Model::Klein => "klein",
+        Model::Poincare => "poincare",
+    };
+    let path = PathBuf::from(EXPORT_DIR).join(format!("{}-{}.svg", stem, suffix));
+
+    let content = write_map(map, model, &HyperPoint::inverse_isometry(map.get_transform()));
+    fs::create_dir_all(EXPORT_DIR).map_err(|e| e.to_string())?;
+    fs::write(&path, content).map_err(|e| e.to_string())?;
+    Ok(path)
+}
+
+/// Start position in the exported Poincaré disk, and the heading there.
+fn export_pose(start: &StartPose, isometry: &Matrix3<f64>) -> ((f64, f64), f64) {
+    // The Poincaré model is conformal, so the heading is the angle
+    // towards a point just ahead of the start.
+    let (x, y) = (start.position.0.x, start.position.0.y);
+    let step = 1e-6;
+    let ahead = PoincarePoint::new(x + step * start.heading.cos(), y + step * start.heading.sin());
+    let mut position = HyperPoint::from(start.position.clone());
+    let mut ahead = HyperPoint::from(ahead);
+    position.transform(isometry);
+    ahead.transform(isometry);
+    let (position, ahead) = (poincare(position), poincare(ahead));
+    (position, (ahead.1 - position.1).atan2(ahead.0 - position.0))
+}
+
+fn klein(point: HyperPoint) -> (f64, f64) {
+    let point = KleinPoint::from(point);
+    (point.0.x, point.0.y)
+}
+
+fn poincare(point: HyperPoint) -> (f64, f64) {
+    let point = PoincarePoint::from(point);
+    (point.0.x, point.0.y)
+}
+
+/// Converts a point of the unit disk to the canvas.
+fn to_canvas(point: (f64, f64)) -> (f64, f64) {
+    let radius = CANVAS_SIZE / 2.;
+    (radius * (point.0 + 1.), radius * (point.1 + 1.))
+}
+
+fn line(a: (f64, f64), b: (f64, f64), texture: &str) -> element::Line {
+    element::Line::new()
+        .set("x1", a.0)
+        .set("y1", a.1)
+        .set("x2", b.0)
+        .set("y2", b.1)
+        .set("class", texture)
+        .set("stroke", "black")
+        .set("stroke-width", 4)
+}
+
+/// Adds the geodesic of the Poincaré disk between two points: an arc of
+/// the circle through them orthogonal to the boundary, or a line through
+/// the center.
+fn add_geodesic(
+    group: element::Group,
+    a: (f64, f64),
+    b: (f64, f64),
+    wall: &HyperWall,
+) -> element::Group {
+    let cross = a.0 * b.1 - a.1 * b.0;
+    let length = (a.0 - b.0).hypot(a.1 - b.1);
+    if cross.abs() <= STRAIGHT_TOLERANCE * length {
+        return group.add(line(to_canvas(a), to_canvas(b), &wall.texture).set("data-height", wall.height));
+    }
+
+    // The center c of a circle orthogonal to the boundary through p
+    // satisfies c.p = (1 + |p|^2) / 2.
+    let ka = (1. + a.0 * a.0 + a.1 * a.1) / 2.;
+    let kb = (1. + b.0 * b.0 + b.1 * b.1) / 2.;
+    let center = ((ka * b.1 - kb * a.1) / cross, (a.0 * kb - b.0 * ka) / cross);
+    let radius = (center.0 * center.0 + center.1 * center.1 - 1.).sqrt();
+
+    // SVG picks between the two centers of a short arc with the sweep flag.
+    let half = ((a.0 - b.0) / 2., (a.1 - b.1) / 2.);
+    let side = (center.0 - (a.0 + b.0) / 2.) * half.1 - (center.1 - (a.1 + b.1) / 2.) * half.0;
+    let sweep = if side > 0. { 1 } else { 0 };
+
+    let (from, to) = (to_canvas(a), to_canvas(b));
+    let radius = radius * CANVAS_SIZE / 2.;
+    let data = Data::new()
+        .move_to((from.0 as f32, from.1 as f32))
+        .elliptical_arc_to((
+            radius as f32,
+            radius as f32,
+            0.,
+            0.,
+            sweep as f32,
+            to.0 as f32,
+            to.1 as f32,
+        ));
+    group.add(
+        element::Path::new()
+            .set("d", data)
+            .set("class", wall.texture.as_str())
+            .set("data-height", wall.height)
+            .set("fill", "none")
+            .set("stroke", "black")
+            .set("stroke-width", 4),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::mapfile::Light;
+    use crate::game::svgloader::load_map;
+    use crate::utils::point::Point;
+
+    fn assert_same_map(a: &HyperMap, b: &HyperMap, tolerance: f64) {
+        let points = |map: &HyperMap| -> Vec<HyperPoint> {
+            map.get_walls_iter()
+                .flat_map(|w| vec![w.beginning, w.end])
+                .chain(map.get_objects_iter().map(|o| o.position))
+                .collect()
+        };
+        let (a, b) = (points(a), points(b));
+        assert_eq!(a.len(), b.len());
+        for (p, q) in a.iter().zip(b.iter()) {
+            assert!((p.0 - q.0).norm() < tolerance, "{:?} != {:?}", p, q);
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut map = load_map(include_str!("../../assets/map3.svg")).unwrap();
+        let mut info = map.get_info().clone();
+        info.lights.push(Light {
+            position: PoincarePoint::new(0.2, -0.1),
+            intensity: 1.5,
+            color: [255, 128, 0],
+        });
+        map.set_info(info);
+        for &model in &[Model::Klein, Model::Poincare] {
+            let written = write_map(&map, model, &Matrix3::identity());
+            let loaded = load_map(&written).unwrap();
+            assert_same_map(&map, &loaded, 1e-2);
+            let wall = map.get_walls_iter().next().unwrap();
+            let loaded_wall = loaded.get_walls_iter().next().unwrap();
+            assert_eq!(loaded_wall.texture, wall.texture);
+            assert_eq!(loaded_wall.height, wall.height);
+            let size = map.get_objects_iter().next().unwrap().size;
+            assert!((loaded.get_objects_iter().next().unwrap().size - size).abs() < 1e-9);
+            let light = &loaded.get_info().lights[0];
+            assert!((light.position.0 - PoincarePoint::new(0.2, -0.1).0).norm() < 1e-6);
+            assert_eq!((light.intensity, light.color), (1.5, [255, 128, 0]));
+        }
+    }
+
+    #[test]
+    fn test_recentre() {
+        let map = load_map(include_str!("../../assets/map3.svg")).unwrap();
+        let object = map.get_objects_iter().next().unwrap().position;
+        let written = write_map(&map, Model::Klein, &object.centering_isometry());
+        let loaded = load_map(&written).unwrap();
+        let position = loaded.get_objects_iter().next().unwrap().position;
+        assert!(position.distance_to_origin() < 1e-3);
+    }
+}
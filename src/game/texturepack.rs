@@ -0,0 +1,137 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use image::RgbaImage;
+use serde::Deserialize;
+
+use super::maps;
+use crate::constants::*;
+
+/// How a texture is drawn on walls.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct TextureEntry {
+    /// Image file, relative to the manifest.
+    pub file: String,
+    /// World units covered by one repeat of the texture.
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    /// Color the texture is multiplied with, as RGBA from 0 to 255.
+    #[serde(default = "default_tint")]
+    pub tint: [u8; 4],
+}
+
+fn default_scale() -> f64 {
+    WALL_HEIGHT as f64
+}
+
+fn default_tint() -> [u8; 4] {
+    [255, 255, 255, 255]
+}
+
+/// Textures of the maps in a directory, listed in a `TEXTURE_MANIFEST`
+/// file next to them. They take precedence over the bundled ones.
+#[derive(Debug, Deserialize)]
+pub struct TextureManifest {
+    pub textures: BTreeMap<String, TextureEntry>,
+    /// Directory the files are relative to.
+    #[serde(skip)]
+    pub dir: PathBuf,
+}
+
+impl TextureManifest {
+    /// Reads the manifest next to a map file, if there is one.
+    /// Bundled maps have none.
+    pub fn for_map(map_name: &str) -> Result<Option<TextureManifest>, String> {
+        if maps::is_builtin(map_name) {
+            return Ok(None);
+        }
+        let dir = Path::new(map_name).parent().unwrap_or_else(|| Path::new(""));
+        let path = dir.join(TEXTURE_MANIFEST);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        TextureManifest::parse(&content, dir)
+            .map(Some)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(content: &str, dir: &Path) -> Result<TextureManifest, String> {
+        let mut manifest: TextureManifest =
+            serde_json::from_str(content).map_err(|e| e.to_string())?;
+        if let Some((name, _)) = manifest
+            .textures
+            .iter()
+            .find(|(_, entry)| !(entry.scale > 0. && entry.scale.is_finite()))
+        {
+            return Err(format!("texture {} has an invalid scale", name));
+        }
+        manifest.dir = dir.to_path_buf();
+        Ok(manifest)
+    }
+
+    /// Path of the image file of an entry.
+    pub fn path(&self, entry: &TextureEntry) -> PathBuf {
+        self.dir.join(&entry.file)
+    }
+
+    /// Reads and decodes the image file of an entry.
+    pub fn load_image(&self, entry: &TextureEntry) -> Result<RgbaImage, String> {
+        let path = self.path(entry);
+        let bytes = fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        image::load_from_memory(&bytes)
+            .map(|image| image.to_rgba8())
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest = TextureManifest::parse(
+            r#"{ "textures": {
+                "BRICK": { "file": "images/brick.png", "scale": 0.05, "tint": [255, 200, 200, 255] },
+                "WALL": { "file": "wall.png" }
+            } }"#,
+            Path::new("maps"),
+        )
+        .unwrap();
+        let brick = &manifest.textures["BRICK"];
+        assert_eq!(brick.scale, 0.05);
+        assert_eq!(brick.tint, [255, 200, 200, 255]);
+        assert_eq!(manifest.path(brick), Path::new("maps/images/brick.png"));
+        let wall = &manifest.textures["WALL"];
+        assert_eq!(wall.scale, WALL_HEIGHT as f64);
+        assert_eq!(wall.tint, [255; 4]);
+
+        let invalid = r#"{ "textures": { "WALL": { "file": "wall.png", "scale": 0 } } }"#;
+        assert!(TextureManifest::parse(invalid, Path::new("")).is_err());
+        assert!(TextureManifest::for_map("map3.svg").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_load_image() {
+        let dir = std::env::temp_dir().join("hypermaze-texturepack-test");
+        fs::create_dir_all(&dir).unwrap();
+        RgbaImage::new(2, 3).save(dir.join("good.png")).unwrap();
+        fs::write(dir.join("bad.png"), "not an image").unwrap();
+        let manifest = TextureManifest::parse(
+            r#"{ "textures": {
+                "GOOD": { "file": "good.png" },
+                "BAD": { "file": "bad.png" },
+                "GONE": { "file": "gone.png" }
+            } }"#,
+            &dir,
+        )
+        .unwrap();
+        let good = manifest.load_image(&manifest.textures["GOOD"]).unwrap();
+        assert_eq!(good.dimensions(), (2, 3));
+        assert!(manifest.load_image(&manifest.textures["BAD"]).is_err());
+        assert!(manifest.load_image(&manifest.textures["GONE"]).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::HashSet;
+use std::f64::consts::PI;
+use std::fmt;
+
+use nalgebra::Matrix3;
+
+use crate::utils::hyperpoint::*;
+use crate::utils::point::Point;
+
+/// A regular tiling of the hyperbolic plane by `p`-gons, `q` of them
+/// meeting at each vertex. One tile is centered at the origin, with a
+/// vertex on the x axis.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tiling {
+    pub p: u32,
+    pub q: u32,
+}
+
+/// A tile of the tiling.
+#[derive(Clone, Debug)]
+pub struct Tile {
+    /// Isometry moving the tile at the origin onto this one.
+    pub isometry: Matrix3<f64>,
+    /// Tiles sharing an edge have different parities,
+    /// if `q` is even so the tiling can be colored that way.
+    pub parity: bool,
+}
+
+impl Tiling {
+    pub fn new(p: u32, q: u32) -> Result<Tiling, String> {
+        // The angles of a regular polygon only add up to less than
+        // a full turn around a vertex in the hyperbolic plane.
+        if p < 3 || q < 3 || (p - 2) * (q - 2) <= 4 {
+            return Err(format!("{{{},{}}} is not a hyperbolic tiling", p, q));
+        }
+        Ok(Tiling { p, q })
+    }
+
+    /// Parses a tiling written as `p,q`.
+    pub fn parse(s: &str) -> Result<Tiling, String> {
+        let numbers: Vec<_> = s.split(',').map(|n| n.trim().parse::<u32>()).collect();
+        match numbers.as_slice() {
+            [Ok(p), Ok(q)] => Tiling::new(*p, *q),
+            _ => Err(format!("Invalid tiling {}, expected p,q", s)),
+        }
+    }
+
+    /// Whether tiles sharing an edge can all get different colors
+    /// with only two of them.
+    pub fn two_colorable(&self) -> bool {
+        self.q.is_multiple_of(2)
+    }
+
+    /// Distance from the center of a tile to its vertices.
+    pub fn circumradius(&self) -> f64 {
+        let (p, q) = (PI / self.p as f64, PI / self.q as f64);
+        (1. / (p.tan() * q.tan())).acosh()
+    }
+
+    /// Distance from the center of a tile to the middle of its edges.
+    pub fn inradius(&self) -> f64 {
+        let (p, q) = (PI / self.p as f64, PI / self.q as f64);
+        (q.cos() / p.sin()).acosh()
+    }
+
+    /// Vertices of the tile at the origin, counterclockwise.
+    pub fn vertices(&self) -> Vec<HyperPoint> {
+        let radius = self.circumradius();
+        (0..self.p)
+            .map(|k| {
+                let angle = 2. * PI * k as f64 / self.p as f64;
+                HyperPoint::new(radius.sinh() * angle.cos(), radius.sinh() * angle.sin())
+            })
+            .collect()
+    }
+
+    /// The tiles sharing an edge with the given one, the `k`-th one
+    /// across the edge between vertices `k` and `k + 1`.
+    pub fn neighbours(&self, tile: &Tile) -> Vec<Tile> {
+        let p = self.p as f64;
+        let step = HyperPoint::translation_matrix(2. * self.inradius(), 0.)
+            * HyperPoint::rotation_matrix(PI - PI / p);
+        (0..self.p)
+            .map(|k| Tile {
+                isometry: tile.isometry
+                    * HyperPoint::rotation_matrix(PI / p + 2. * PI * k as f64 / p)
+                    * step,
+                parity: !tile.parity,
+            })
+            .collect()
+    }
+
+    /// The tiles whose centers are within `radius` of the center of `tile`.
+    pub fn patch(&self, tile: &Tile, radius: f64) -> Vec<Tile> {
+        let center = |tile: &Tile| {
+            let mut point = HyperPoint::new(0., 0.);
+            point.transform(&tile.isometry);
+            point
+        };
+        let origin = center(tile);
+        let key = |point: &HyperPoint| ((point.0.x * 1e4).round() as i64, (point.0.y * 1e4).round() as i64);
+
+        let mut seen = HashSet::new();
+        seen.insert(key(&origin));
+        let mut tiles = vec![tile.clone()];
+        let mut i = 0;
+        while i < tiles.len() {
+            for neighbour in self.neighbours(&tiles[i]) {
+                let point = center(&neighbour);
+                if point.distance_to(&origin) <= radius && seen.insert(key(&point)) {
+                    tiles.push(neighbour);
+                }
+            }
+            i += 1;
+        }
+        tiles
+    }
+
+    /// The tile containing a point, found by walking from `tile`
+    /// to the neighbour closest to the point until there is none closer.
+    pub fn tile_at(&self, tile: &Tile, point: &HyperPoint) -> Tile {
+        let distance = |tile: &Tile| {
+            let mut point = *point;
+            point.transform(&HyperPoint::inverse_isometry(&tile.isometry));
+            point.0.z
+        };
+        let mut tile = tile.clone();
+        loop {
+            let closest = self
+                .neighbours(&tile)
+                .into_iter()
+                .map(|neighbour| (distance(&neighbour), neighbour))
+                .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+            match closest {
+                Some((d, neighbour)) if d < distance(&tile) - 1e-12 => tile = neighbour,
+                _ => return tile,
+            }
+        }
+    }
+}
+
+impl fmt::Display for Tiling {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{{{},{}}}", self.p, self.q)
+    }
+}
+
+impl Tile {
+    /// The tile at the origin.
+    pub fn origin() -> Tile {
+        Tile {
+            isometry: Matrix3::identity(),
+            parity: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn same(a: &HyperPoint, b: &HyperPoint) -> bool {
+        (a.0 - b.0).norm() < 1e-9
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Tiling::parse("7,3"), Ok(Tiling { p: 7, q: 3 }));
+        assert_eq!(Tiling::parse(" 5, 4").unwrap().to_string(), "{5,4}");
+        assert!(Tiling::parse("4,4").is_err());
+        assert!(Tiling::parse("6,3").is_err());
+        assert!(Tiling::parse("7").is_err());
+        assert!(Tiling::parse("a,3").is_err());
+    }
+
+    #[test]
+    fn test_neighbours() {
+        for &(p, q) in &[(7, 3), (5, 4), (4, 5)] {
+            let tiling = Tiling::new(p, q).unwrap();
+            let vertices = tiling.vertices();
+            // The k-th neighbour shares vertices k and k + 1.
+            for (k, neighbour) in tiling.neighbours(&Tile::origin()).iter().enumerate() {
+                let shared: Vec<_> = tiling
+                    .vertices()
+                    .into_iter()
+                    .map(|mut v| {
+                        v.transform(&neighbour.isometry);
+                        v
+                    })
+                    .collect();
+                for v in &[&vertices[k], &vertices[(k + 1) % p as usize]] {
+                    assert!(shared.iter().any(|s| same(s, v)));
+                }
+            }
+
+            // q tiles meet at each vertex.
+            let at_vertex = tiling
+                .patch(&Tile::origin(), 3. * tiling.circumradius())
+                .iter()
+                .filter(|tile| {
+                    let mut center = HyperPoint::new(0., 0.);
+                    center.transform(&tile.isometry);
+                    (center.distance_to(&vertices[0]) - tiling.circumradius()).abs() < 1e-6
+                })
+                .count();
+            assert_eq!(at_vertex, q as usize);
+        }
+    }
+
+    #[test]
+    fn test_tile_at() {
+        let tiling = Tiling::new(5, 4).unwrap();
+        let point = HyperPoint::new(2., -1.5);
+        let tile = tiling.tile_at(&Tile::origin(), &point);
+        let mut center = HyperPoint::new(0., 0.);
+        center.transform(&tile.isometry);
+        assert!(center.distance_to(&point) <= tiling.circumradius());
+    }
+}
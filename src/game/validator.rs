@@ -0,0 +1,495 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use super::hypermap::HyperMap;
+use super::maps;
+use crate::utils::hyperpoint::*;
+use crate::utils::kleinpoint::*;
+
+/// Cells of the grid searched for paths, along a diameter of the Klein disk.
+const GRID_SIZE: usize = 160;
+
+/// Points of the Klein disk closer than this are the same.
+const TOLERANCE: f64 = 1e-6;
+
+/// Walls crossing this close to the end of one of them
+/// overshoot a corner, which is not reported.
+const CORNER_TOLERANCE: f64 = 0.02;
+
+/// Part of a map a problem is about. Walls and pickups are numbered
+/// from 0, in the order of the map file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Item {
+    Wall(usize),
+    Pickup(usize),
+    Start,
+    Exit,
+    Light(usize),
+}
+
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Item::Wall(index) => write!(f, "wall {}", index),
+            Item::Pickup(index) => write!(f, "pickup {}", index),
+            Item::Start => write!(f, "start"),
+            Item::Exit => write!(f, "exit"),
+            Item::Light(index) => write!(f, "light {}", index),
+        }
+    }
+}
+
+/// What is wrong with an item.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProblemKind {
+    /// A point is on or outside the boundary of the disk.
+    OutsideDisk,
+    ZeroLength,
+    /// Same ends as the given wall.
+    Duplicate(usize),
+    /// Crosses the given wall.
+    Crossing(usize),
+    /// The texture is not one the renderer knows.
+    UnknownTexture(String),
+    /// A pickup overlaps the given wall.
+    InsideWall(usize),
+    /// Cannot be walked to from the start.
+    Unreachable,
+}
+
+impl fmt::Display for ProblemKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProblemKind::OutsideDisk => write!(f, "on or outside the disk"),
+            ProblemKind::ZeroLength => write!(f, "zero length"),
+            ProblemKind::Duplicate(wall) => write!(f, "duplicate of wall {}", wall),
+            ProblemKind::Crossing(wall) => write!(f, "crosses wall {}", wall),
+            ProblemKind::UnknownTexture(texture) => write!(f, "unknown texture \"{}\"", texture),
+            ProblemKind::InsideWall(wall) => write!(f, "inside wall {}", wall),
+            ProblemKind::Unreachable => write!(f, "unreachable from the start"),
+        }
+    }
+}
+
+/// A problem found in a map.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Problem {
+    pub item: Item,
+    /// Where the problem is, in the Klein disk in world coordinates.
+    /// None when the point is outside the disk.
+    pub at: Option<(f64, f64)>,
+    pub kind: ProblemKind,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.at {
+            Some((x, y)) => write!(f, "{} at ({:.4}, {:.4}): {}", self.item, x, y, self.kind),
+            None => write!(f, "{}: {}", self.item, self.kind),
+        }
+    }
+}
+
+/// A wall in world coordinates of the Klein disk.
+struct Segment {
+    a: (f64, f64),
+    b: (f64, f64),
+}
+
+/// Checks a map for problems, given the names of the known textures.
+pub fn validate(map: &HyperMap, textures: &[String]) -> Vec<Problem> {
+    let to_world = HyperPoint::inverse_isometry(map.get_transform());
+    let world = |point: &HyperPoint| {
+        let mut point = *point;
+        point.transform(&to_world);
+        klein(&point)
+    };
+    let mut problems = vec![];
+
+    let mut segments = vec![];
+    for (index, wall) in map.get_walls_iter().enumerate() {
+        let item = Item::Wall(index);
+        let (a, b) = (world(&wall.beginning), world(&wall.end));
+        if !textures.contains(&wall.texture) {
+            problems.push(Problem {
+                item,
+                at: a.or(b),
+                kind: ProblemKind::UnknownTexture(wall.texture.clone()),
+            });
+        }
+        match (a, b) {
+            (Some(a), Some(b)) => segments.push((index, Segment { a, b })),
+            _ => problems.push(Problem {
+                item,
+                at: a.or(b),
+                kind: ProblemKind::OutsideDisk,
+            }),
+        }
+    }
+
+    for (i, (first, wall)) in segments.iter().enumerate() {
+        if distance(wall.a, wall.b) < TOLERANCE {
+            problems.push(Problem {
+                item: Item::Wall(*first),
+                at: Some(wall.a),
+                kind: ProblemKind::ZeroLength,
+            });
+            continue;
+        }
+        for (second, other) in &segments[i + 1..] {
+            if is_duplicate(wall, other) {
+                problems.push(Problem {
+                    item: Item::Wall(*second),
+                    at: Some(other.a),
+                    kind: ProblemKind::Duplicate(*first),
+                });
+            } else if let Some(at) = crossing(wall.a, wall.b, other.a, other.b) {
+                let ends = [wall.a, wall.b, other.a, other.b];
+                if ends.iter().all(|&end| distance(at, end) >= CORNER_TOLERANCE) {
+                    problems.push(Problem {
+                        item: Item::Wall(*second),
+                        at: Some(at),
+                        kind: ProblemKind::Crossing(*first),
+                    });
+                }
+            }
+        }
+    }
+
+    // Pickups overlap walls closer than their radius. The distance is
+    // measured with the pickup at the center, where the Klein disk
+    // is the least distorted.
+    let mut pickups = vec![];
+    for (index, object) in map.get_objects_iter().enumerate() {
+        let item = Item::Pickup(index);
+        let at = match world(&object.position) {
+            Some(at) => at,
+            None => {
+                problems.push(Problem {
+                    item,
+                    at: None,
+                    kind: ProblemKind::OutsideDisk,
+                });
+                continue;
+            }
+        };
+        let centering = object.position.centering_isometry();
+        let centered = |point: &HyperPoint| {
+            let mut point = *point;
+            point.transform(&centering);
+            klein(&point)
+        };
+        for (wall_index, wall) in map.get_walls_iter().enumerate() {
+            if let (Some(a), Some(b)) = (centered(&wall.beginning), centered(&wall.end)) {
+                if distance_to_segment((0., 0.), a, b) < object.size {
+                    problems.push(Problem {
+                        item,
+                        at: Some(at),
+                        kind: ProblemKind::InsideWall(wall_index),
+                    });
+                }
+            }
+        }
+        pickups.push((item, at));
+    }
+
+    let info = map.get_info();
+    let start = klein(&HyperPoint::from(info.start.position.clone()));
+    let exit = info
+        .exit
+        .as_ref()
+        .map(|exit| klein(&HyperPoint::from(exit.clone())));
+    if start.is_none() {
+        problems.push(Problem {
+            item: Item::Start,
+            at: None,
+            kind: ProblemKind::OutsideDisk,
+        });
+    }
+    match exit {
+        Some(Some(at)) => pickups.push((Item::Exit, at)),
+        Some(None) => problems.push(Problem {
+            item: Item::Exit,
+            at: None,
+            kind: ProblemKind::OutsideDisk,
+        }),
+        None => (),
+    }
+    for (index, light) in info.lights.iter().enumerate() {
+        if klein(&HyperPoint::from(light.position.clone())).is_none() {
+            problems.push(Problem {
+                item: Item::Light(index),
+                at: None,
+                kind: ProblemKind::OutsideDisk,
+            });
+        }
+    }
+
+    if let Some(start) = start {
+        let grid = Grid::new(segments.iter().map(|(_, wall)| wall));
+        let reached = grid.reachable_from(start);
+        for (item, at) in pickups {
+            if !grid.cell(at).is_some_and(|cell| reached[cell]) {
+                problems.push(Problem {
+                    item,
+                    at: Some(at),
+                    kind: ProblemKind::Unreachable,
+                });
+            }
+        }
+    }
+    problems
+}
+
+/// Loads and checks the map files, printing the problems found
+/// and the elements skipped while loading them.
+/// `textures` gives the names of the textures a map can use.
+/// Returns whether all of them are fine.
+pub fn validate_files(paths: &[String], textures: impl Fn(&str) -> Vec<String>) -> bool {
+    let mut valid = true;
+    for path in paths {
+        match maps::load_with_warnings(path) {
+            Ok((map, warnings)) => {
+                for warning in warnings {
+                    eprintln!("{}: warning: {}", path, warning);
+                }
+                for problem in validate(&map, &textures(path)) {
+                    eprintln!("{}: {}", path, problem);
+                    valid = false;
+                }
+            }
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                valid = false;
+            }
+        }
+    }
+    valid
+}
+
+/// Square grid over the Klein disk, with the walls crossing each cell,
+/// used to find which places can be walked to.
+struct Grid<'a> {
+    walls: Vec<Vec<&'a Segment>>,
+}
+
+impl<'a> Grid<'a> {
+    fn new(walls: impl Iterator<Item = &'a Segment>) -> Grid<'a> {
+        let mut grid = Grid {
+            walls: vec![vec![]; GRID_SIZE * GRID_SIZE],
+        };
+        for wall in walls {
+            let (x1, y1) = Self::coordinates(wall.a.0.min(wall.b.0), wall.a.1.min(wall.b.1));
+            let (x2, y2) = Self::coordinates(wall.a.0.max(wall.b.0), wall.a.1.max(wall.b.1));
+            for x in x1..=x2 {
+                for y in y1..=y2 {
+                    grid.walls[x * GRID_SIZE + y].push(wall);
+                }
+            }
+        }
+        grid
+    }
+
+    /// Column and row of the cell containing a point, clamped to the grid.
+    fn coordinates(x: f64, y: f64) -> (usize, usize) {
+        let index = |v: f64| (((v + 1.) / 2. * GRID_SIZE as f64) as usize).min(GRID_SIZE - 1);
+        (index(x.max(-1.)), index(y.max(-1.)))
+    }
+
+    fn center(cell: usize) -> (f64, f64) {
+        let side = 2. / GRID_SIZE as f64;
+        let (x, y) = (cell / GRID_SIZE, cell % GRID_SIZE);
+        (-1. + (x as f64 + 0.5) * side, -1. + (y as f64 + 0.5) * side)
+    }
+
+    /// Cell containing a point, if it lies inside the disk.
+    fn cell(&self, point: (f64, f64)) -> Option<usize> {
+        let (x, y) = Self::coordinates(point.0, point.1);
+        let cell = x * GRID_SIZE + y;
+        let side = 2. / GRID_SIZE as f64;
+        let center = Self::center(cell);
+        if distance(center, (0., 0.)) < 1. - side {
+            Some(cell)
+        } else {
+            None
+        }
+    }
+
+    /// Cells which can be walked to from a point, without crossing walls.
+    fn reachable_from(&self, start: (f64, f64)) -> Vec<bool> {
+        let mut reached = vec![false; GRID_SIZE * GRID_SIZE];
+        let mut queue = VecDeque::new();
+        if let Some(cell) = self.cell(start) {
+            reached[cell] = true;
+            queue.push_back(cell);
+        }
+        while let Some(cell) = queue.pop_front() {
+            let (x, y) = (cell / GRID_SIZE, cell % GRID_SIZE);
+            let neighbours = [
+                (x.wrapping_sub(1), y),
+                (x + 1, y),
+                (x, y.wrapping_sub(1)),
+                (x, y + 1),
+            ];
+            for &(nx, ny) in &neighbours {
+                if nx >= GRID_SIZE || ny >= GRID_SIZE {
+                    continue;
+                }
+                let next = nx * GRID_SIZE + ny;
+                if reached[next] || self.cell(Self::center(next)).is_none() {
+                    continue;
+                }
+                let (a, b) = (Self::center(cell), Self::center(next));
+                let blocked = self.walls[cell]
+                    .iter()
+                    .chain(self.walls[next].iter())
+                    .any(|wall| crossing(a, b, wall.a, wall.b).is_some());
+                if !blocked {
+                    reached[next] = true;
+                    queue.push_back(next);
+                }
+            }
+        }
+        reached
+    }
+}
+
+/// Point of the Klein disk, if the point is inside it.
+fn klein(point: &HyperPoint) -> Option<(f64, f64)> {
+    let finite = point.0.iter().all(|v| v.is_finite());
+    if !finite || point.0.z <= 0. {
+        return None;
+    }
+    let point = KleinPoint::from(*point);
+    Some((point.0.x, point.0.y))
+}
+
+fn distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    (a.0 - b.0).hypot(a.1 - b.1)
+}
+
+fn is_duplicate(first: &Segment, second: &Segment) -> bool {
+    let same = |a, b| distance(a, b) < TOLERANCE;
+    (same(first.a, second.a) && same(first.b, second.b))
+        || (same(first.a, second.b) && same(first.b, second.a))
+}
+
+/// Point where two segments cross, if their interiors do.
+/// Segments which only touch, e.g. at a shared end, don't cross.
+fn crossing(a: (f64, f64), b: (f64, f64), c: (f64, f64), d: (f64, f64)) -> Option<(f64, f64)> {
+    let cross = |o: (f64, f64), p: (f64, f64), q: (f64, f64)| {
+        (p.0 - o.0) * (q.1 - o.1) - (p.1 - o.1) * (q.0 - o.0)
+    };
+    let (d1, d2) = (cross(a, b, c), cross(a, b, d));
+    let (d3, d4) = (cross(c, d, a), cross(c, d, b));
+    let epsilon = TOLERANCE * TOLERANCE;
+    let opposite = |u: f64, v: f64| (u > epsilon && v < -epsilon) || (u < -epsilon && v > epsilon);
+    if opposite(d1, d2) && opposite(d3, d4) {
+        let t = d3 / (d3 - d4);
+        Some((a.0 + t * (b.0 - a.0), a.1 + t * (b.1 - a.1)))
+    } else {
+        None
+    }
+}
+
+pub(crate) fn distance_to_segment(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length = dx * dx + dy * dy;
+    if length == 0. {
+        return distance(p, a);
+    }
+    let t = (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / length).clamp(0., 1.);
+    distance(p, (a.0 + t * dx, a.1 + t * dy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::svgloader::load_map;
+    use crate::utils::poincarepoint::PoincarePoint;
+
+    fn textures() -> Vec<String> {
+        vec!["WALL".to_string()]
+    }
+
+    fn svg(body: &str) -> String {
+        format!(
+            "<svg width=\"2000\" height=\"2000\" xmlns=\"http://www.w3.org/2000/svg\">{}</svg>",
+            body
+        )
+    }
+
+    fn kinds(body: &str) -> Vec<(Item, ProblemKind)> {
+        let map = load_map(&svg(body)).unwrap();
+        validate(&map, &textures())
+            .into_iter()
+            .map(|problem| (problem.item, problem.kind))
+            .collect()
+    }
+
+    #[test]
+    fn test_walls() {
+        assert!(kinds("<line x1=\"1200\" y1=\"800\" x2=\"1200\" y2=\"1200\"/>").is_empty());
+        assert_eq!(
+            kinds("<line x1=\"1200\" y1=\"800\" x2=\"1200\" y2=\"800\"/>"),
+            vec![(Item::Wall(0), ProblemKind::ZeroLength)]
+        );
+        assert_eq!(
+            kinds("<line x1=\"1200\" y1=\"800\" x2=\"2200\" y2=\"800\"/>"),
+            vec![(Item::Wall(0), ProblemKind::OutsideDisk)]
+        );
+        assert_eq!(
+            kinds("<line class=\"GOLD\" x1=\"1200\" y1=\"800\" x2=\"1200\" y2=\"900\"/>"),
+            vec![(Item::Wall(0), ProblemKind::UnknownTexture("GOLD".to_string()))]
+        );
+        assert_eq!(
+            kinds(
+                "<line x1=\"1200\" y1=\"800\" x2=\"1200\" y2=\"1200\"/>\
+                 <line x1=\"1200\" y1=\"1200\" x2=\"1200\" y2=\"800\"/>\
+                 <line x1=\"1100\" y1=\"1000\" x2=\"1300\" y2=\"1000\"/>"
+            ),
+            vec![
+                (Item::Wall(1), ProblemKind::Duplicate(0)),
+                (Item::Wall(2), ProblemKind::Crossing(0)),
+                (Item::Wall(2), ProblemKind::Crossing(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pickups() {
+        assert_eq!(
+            kinds("<line x1=\"1200\" y1=\"800\" x2=\"1200\" y2=\"1200\"/><ellipse cx=\"1205\" cy=\"1000\"/>"),
+            vec![(Item::Pickup(0), ProblemKind::InsideWall(0))]
+        );
+
+        // A pickup in a closed room, away from the start.
+        let room = "<polygon points=\"1200,800 1400,800 1400,1200 1200,1200\"/>";
+        assert!(kinds(room).is_empty());
+        let problems = kinds(&format!("{}<ellipse cx=\"1300\" cy=\"1000\"/>", room));
+        assert_eq!(problems, vec![(Item::Pickup(0), ProblemKind::Unreachable)]);
+    }
+
+    #[test]
+    fn test_lights() {
+        let mut map = load_map(&svg("<circle class=\"light\" cx=\"500\" cy=\"1000\" r=\"20\"/>")).unwrap();
+        assert!(validate(&map, &textures()).is_empty());
+
+        // JSON maps give the position as is, which can be off the disk.
+        let mut info = map.get_info().clone();
+        info.lights[0].position = PoincarePoint::new(1.2, 0.);
+        map.set_info(info);
+        let problems = validate(&map, &textures());
+        assert_eq!(problems.len(), 1);
+        assert_eq!((problems[0].item, &problems[0].kind), (Item::Light(0), &ProblemKind::OutsideDisk));
+    }
+
+    #[test]
+    fn test_bundled_maps() {
+        let textures = crate::fpp_renderer::FppRenderer::texture_names();
+        for name in &["map.svg", "map2.svg", "map3.svg", "demotext.json", "demolarge.json"] {
+            let map = maps::load(name).unwrap();
+            assert_eq!(validate(&map, &textures), vec![], "{}", name);
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use macroquad::prelude::*;
+
+use crate::constants::*;
+use crate::game::Game;
+use crate::utils::hyperpoint::HyperPoint;
+use crate::utils::point::Point;
+
+/// A light moved into the view.
+struct ViewLight {
+    position: HyperPoint,
+    intensity: f32,
+    color: Color,
+}
+
+/// Lights of a frame, in the view, with which the first person view
+/// shades each vertex. Everything fades into fog with its distance.
+pub struct Lighting {
+    lights: Vec<ViewLight>,
+    ambient: f32,
+    flashlight: bool,
+}
+
+impl Lighting {
+    pub fn new(game: &Game) -> Lighting {
+        let info = game.map.get_info();
+        let lights = info
+            .lights
+            .iter()
+            .map(|light| {
+                let mut position = HyperPoint::from(light.position.clone());
+                position.transform(game.map.get_transform());
+                let [r, g, b] = light.color;
+                ViewLight {
+                    position,
+                    intensity: light.intensity as f32,
+                    color: Color::from_rgba(r, g, b, 255),
+                }
+            })
+            .collect();
+        Lighting {
+            lights,
+            // Maps without lights are lit evenly, as they always were.
+            ambient: if info.lights.is_empty() {
+                UNLIT_AMBIENT_LIGHT
+            } else {
+                AMBIENT_LIGHT
+            },
+            flashlight: game.flashlight,
+        }
+    }
+
+    /// Color of a surface of the given color at a point of the view,
+    /// lit and fogged.
+    pub fn shade(&self, point: &HyperPoint, color: Color) -> Color {
+        let mut light = [self.ambient; 3];
+        for source in &self.lights {
+            let brightness = source.intensity * falloff(source.position.distance_to(point));
+            light[0] += source.color.r * brightness;
+            light[1] += source.color.g * brightness;
+            light[2] += source.color.b * brightness;
+        }
+        let distance = point.distance_to_origin();
+        if self.flashlight {
+            let brightness = FLASHLIGHT_INTENSITY * falloff(distance) * cone(point);
+            light.iter_mut().for_each(|l| *l += brightness);
+        }
+
+        let lit = Color::new(
+            (color.r * light[0]).min(1.),
+            (color.g * light[1]).min(1.),
+            (color.b * light[2]).min(1.),
+            color.a,
+        );
+        fog_color(lit, fog(distance))
+    }
+
+    /// Color of something giving off its own light, fogged.
+    pub fn fog_only(&self, point: &HyperPoint, color: Color) -> Color {
+        fog_color(color, fog(point.distance_to_origin()))
+    }
+}
+
+/// Color hidden by the given amount of fog.
+fn fog_color(color: Color, fog: f32) -> Color {
+    Color::new(
+        color.r + (FOG_COLOR.r - color.r) * fog,
+        color.g + (FOG_COLOR.g - color.g) * fog,
+        color.b + (FOG_COLOR.b - color.b) * fog,
+        color.a,
+    )
+}
+
+/// Brightness of a light at the given distance, relative to its intensity.
+/// The light spreads over a circle whose circumference grows as sinh,
+/// and is full within `LIGHT_RADIUS`.
+pub fn falloff(distance: f64) -> f32 {
+    if distance <= LIGHT_RADIUS || distance.is_nan() {
+        return 1.;
+    }
+    (LIGHT_RADIUS.sinh() / distance.sinh()) as f32
+}
+
+/// How much of a surface at the given distance is hidden by fog, from 0 to 1.
+pub fn fog(distance: f64) -> f32 {
+    if !distance.is_finite() {
+        return 1.;
+    }
+    1. - (-FOG_DENSITY * distance).exp() as f32
+}
+
+/// How much of the flashlight's beam reaches a point: all of it straight
+/// ahead, then less and less out to `FLASHLIGHT_ANGLE` off the axis.
+fn cone(point: &HyperPoint) -> f32 {
+    let length = (point.0.x * point.0.x + point.0.y * point.0.y).sqrt();
+    if length < 1e-9 {
+        return 1.;
+    }
+    let edge = FLASHLIGHT_ANGLE.cos();
+    let t = ((point.0.x / length - edge) / (1. - edge)).clamp(0., 1.) as f32;
+    t * t * (3. - 2. * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_falloff() {
+        assert_eq!(falloff(0.), 1.);
+        assert_eq!(falloff(LIGHT_RADIUS / 2.), 1.);
+        // Twice as far, a light spreads over a much longer circle
+        // than it would on a flat floor.
+        let ratio = falloff(2.) / falloff(1.);
+        assert!((ratio as f64 - 1f64.sinh() / 2f64.sinh()).abs() < 1e-6);
+        assert!(ratio < 0.5);
+
+        assert_eq!(fog(0.), 0.);
+        assert!(fog(1.) < fog(2.));
+        assert_eq!(fog(f64::INFINITY), 1.);
+        assert_eq!(cone(&HyperPoint::new(1., 0.)), 1.);
+        assert_eq!(cone(&HyperPoint::new(-1., 0.)), 0.);
+    }
+}
@@ -1,24 +1,47 @@
 mod constants;
+mod edit_mode;
 mod fpp_renderer;
 mod game;
+mod lighting;
+mod minimap;
+mod options;
 mod top_down_renderer;
 mod poincare_renderer;
+mod renderer;
+mod state;
 mod utils;
 
-use constants::*;
-use fpp_renderer::*;
-use game::*;
 use macroquad::{prelude::*, ui::*};
-use top_down_renderer::*;
-use poincare_renderer::*;
-use svgloader::*;
+use options::*;
+use state::*;
 
 /// Creates window configuration.
 fn window_conf() -> Conf {
+    let options = Options::from_env();
+    // Converting and validating need no window, so they are done before one is opened.
+    if let Some((input, output)) = &options.convert {
+        match game::mapfile::convert(input, output, options.format) {
+            Ok(warnings) => {
+                for warning in warnings {
+                    eprintln!("Warning: {}", warning);
+                }
+                std::process::exit(0)
+            }
+            Err(e) => {
+                eprintln!("Could not convert the map: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if !options.validate.is_empty() {
+        let textures = fpp_renderer::FppRenderer::texture_names_for;
+        let valid = game::validator::validate_files(&options.validate, textures);
+        std::process::exit(if valid { 0 } else { 1 });
+    }
     Conf {
         window_title: "HyperMaze".to_owned(),
-        window_width: GAME_SIZE_X,
-        window_height: GAME_SIZE_Y,
+        window_width: options.width,
+        window_height: options.height,
         ..Default::default()
     }
 }
@@ -54,11 +77,20 @@ fn show_loading() {
     root_ui().label(None, "Loading...");
     root_ui().label(None, "Tip: Press TAB for minimap");
     root_ui().label(None, "Tip: Press P for Poincare view");
+    root_ui().label(None, "Tip: Press V to cycle through the views");
+    root_ui().label(None, "Tip: Press F for a flashlight");
+    root_ui().label(None, "Tip: Drag the disk views to pan them, C to recentre");
+    root_ui().label(None, "Tip: Press ESC to pause");
 }
 
 /// Main function.
 #[macroquad::main(window_conf)]
 async fn main() {
+    let options = Options::from_env();
+    if let Some(seed) = options.seed {
+        rand::srand(seed);
+    }
+
     // Apply styles
     style_ui();
     
@@ -68,57 +100,16 @@ async fn main() {
     next_frame().await;
     next_frame().await;
 
-    // Initialize the game
-    let map = load_map(include_str!("../assets/map3.svg"));
-    let mut game = Game::new(map);
-
-    // Initialize the renderers. This takes a bit of time
-    // because it needs to load the textures.
-    let fpp_renderer = FppRenderer::new();
-    let top_down_renderer = TopDownRenderer::new();
-    let poincare_renderer = PoincareRenderer::new();
-
-    loop {
-        // Update the game
-        let movement = if is_key_down(KEY_FASTER) {
-            MOVEMENT_SPEED * get_frame_time() as f64 * 2.
-        } else {
-            MOVEMENT_SPEED * get_frame_time() as f64
-        };
-        let rotation = ROTATION_SPEED * get_frame_time() as f64;
-        if is_key_down(KEY_FORWARD) || is_key_down(KEY_FORWARD_ALT) {
-            game.move_player(-movement);
-        }
-        if is_key_down(KEY_BACKWARD) || is_key_down(KEY_BACKWARD_ALT) {
-            game.move_player(movement);
-        }
-        if is_key_down(KEY_STRAFE_L) {
-            game.strafe_player(movement);
-        }
-        if is_key_down(KEY_STRAFE_R) {
-            game.strafe_player(-movement);
-        }
-        if is_key_down(KEY_LEFT) {
-            game.rotate_player(-rotation);
-        }
-        if is_key_down(KEY_RIGHT) {
-            game.rotate_player(rotation);
-        }
-        if is_key_down(KEY_EXIT) {
-            std::process::exit(0);
-        }
-
-        game.tick();
+    let renderers = Renderers::new(&options);
+    let mut states = StateStack::new(State::menu());
+    if let Some(map) = &options.map {
+        states.push(State::start(map));
+    }
+    if let Some(map) = &options.edit {
+        states.push(State::edit(map));
+    }
 
-        // Render the game
-        if is_key_down(KEY_CHANGE_VIEW) {
-            top_down_renderer.render(&game);
-        } else if is_key_down(macroquad::prelude::KeyCode::P) {
-            poincare_renderer.render(&game);
-        } else {
-            fpp_renderer.render(&game);
-        }
-        game.display_hud();
+    while states.update(&renderers) {
         next_frame().await
     }
 }
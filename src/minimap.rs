@@ -0,0 +1,167 @@
+use std::cell::{Cell, RefCell};
+
+use macroquad::prelude::*;
+
+use crate::constants::*;
+use crate::game::Game;
+use crate::renderer::{ViewRegistry, ViewSettings};
+use crate::top_down_renderer::disk_camera;
+use crate::utils::hyperpoint::HyperPoint;
+
+/// How the minimap in the corner of the screen is drawn.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MinimapSettings {
+    /// Name of the view drawn in it: `klein` or `poincare`.
+    pub view: String,
+    /// Side of the minimap, relative to the height of the screen.
+    pub size: f32,
+    pub opacity: f32,
+    pub zoom: f32,
+    /// Whether the player's heading points up, or the map keeps
+    /// the world's axes as the player turns.
+    pub player_up: bool,
+    /// Whether it is shown when the game starts.
+    pub shown: bool,
+}
+
+impl Default for MinimapSettings {
+    fn default() -> Self {
+        MinimapSettings {
+            view: "klein".to_string(),
+            size: 0.3,
+            opacity: 0.8,
+            zoom: 1.,
+            player_up: true,
+            shown: false,
+        }
+    }
+}
+
+/// A disk view drawn into a texture, then over the view being played.
+pub struct Minimap {
+    settings: MinimapSettings,
+    target: RenderTarget,
+    shown: Cell<bool>,
+    /// How the minimap is panned, and zoomed relative to `settings.zoom`.
+    pub view: RefCell<ViewSettings>,
+    /// Point of the plane under the mouse while it drags the minimap.
+    pub dragging: RefCell<Option<HyperPoint>>,
+}
+
+impl Minimap {
+    pub fn new(settings: MinimapSettings) -> Minimap {
+        let target = render_target(MINIMAP_RESOLUTION, MINIMAP_RESOLUTION);
+        target.texture.set_filter(FilterMode::Linear);
+        Minimap {
+            shown: Cell::new(settings.shown),
+            settings,
+            target,
+            view: RefCell::new(ViewSettings::default()),
+            dragging: RefCell::new(None),
+        }
+    }
+
+    /// Shows or hides the minimap when `KEY_MINIMAP` is pressed.
+    pub fn handle_keys(&self) {
+        if is_key_pressed(KEY_MINIMAP) {
+            self.shown.set(!self.shown.get());
+        }
+    }
+
+    /// The view drawn in the minimap, if it is shown over the current one.
+    pub fn view_over(&self, views: &ViewRegistry, current: usize) -> Option<usize> {
+        match views.index_of(&self.settings.view) {
+            Some(view) if self.shown.get() && view != current => Some(view),
+            _ => None,
+        }
+    }
+
+    /// How the minimap's view is drawn in the current frame.
+    pub fn settings(&self, game: &Game, fog_of_war: bool) -> ViewSettings {
+        let view = self.view.borrow();
+        ViewSettings {
+            zoom: self.settings.zoom * view.zoom,
+            rotation: if self.settings.player_up {
+                0.
+            } else {
+                world_rotation(game)
+            },
+            target: Some(self.target),
+            pan: view.pan,
+            fog_of_war,
+        }
+    }
+
+    /// Where the minimap is drawn on the screen.
+    fn rect(&self) -> Rect {
+        let side = self.settings.size * screen_height();
+        Rect::new(screen_width() - side - MINIMAP_MARGIN, MINIMAP_MARGIN, side, side)
+    }
+
+    /// Point of the minimap's disk under the mouse, if the mouse is over it.
+    pub fn mouse(&self, settings: &ViewSettings) -> Option<Vec2> {
+        let (x, y) = mouse_position();
+        let rect = self.rect();
+        if !rect.contains(vec2(x, y)) {
+            return None;
+        }
+        // The target is drawn upright, as the disk would be on a square screen.
+        let camera = Camera2D {
+            render_target: None,
+            ..disk_camera(settings)
+        };
+        let ndc = vec3((x - rect.x) / rect.w * 2. - 1., 1. - (y - rect.y) / rect.h * 2., 0.);
+        Some(camera.matrix().inverse().transform_point3(ndc).truncate())
+    }
+
+    /// Draws the minimap in the top right corner of the screen, if it is
+    /// shown and the view under it is another one. With `fog_of_war`, only
+    /// what the player has explored is drawn.
+    pub fn draw(&self, views: &ViewRegistry, current: usize, game: &Game, fog_of_war: bool) {
+        let view = match self.view_over(views, current) {
+            Some(view) => view,
+            None => return,
+        };
+        views.get(view).render(game, &self.settings(game, fog_of_war));
+
+        set_default_camera();
+        let rect = self.rect();
+        draw_texture_ex(
+            self.target.texture,
+            rect.x,
+            rect.y,
+            Color::new(1., 1., 1., self.settings.opacity),
+            DrawTextureParams {
+                dest_size: Some(vec2(rect.w, rect.h)),
+                ..Default::default()
+            },
+        );
+    }
+}
+
+/// Angle to turn the view by so the world's axes keep their direction.
+/// The view is centered on the player by moving the world along the
+/// geodesic from its origin, then turning it by the player's heading.
+fn world_rotation(game: &Game) -> f32 {
+    let transform = game.map.get_transform();
+    let mut player = HyperPoint::new(0., 0.);
+    player.transform(&HyperPoint::inverse_isometry(transform));
+    let rotation = player.centering_isometry() * HyperPoint::inverse_isometry(transform);
+    rotation[(1, 0)].atan2(rotation[(0, 0)]) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::hypermap::HyperMap;
+
+    #[test]
+    fn test_world_rotation() {
+        let mut game = Game::new(HyperMap::new_with(vec![], vec![]), "test_world_rotation", None);
+        game.rotate_player(0.5);
+        assert!((world_rotation(&game) + 0.5).abs() < 1e-6);
+        // Walking straight ahead doesn't turn the world.
+        game.move_player(-0.3);
+        assert!((world_rotation(&game) + 0.5).abs() < 1e-6);
+    }
+}
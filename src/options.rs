@@ -0,0 +1,247 @@
+use crate::constants::*;
+use crate::game::mapfile::MapFormat;
+use crate::game::tiling::Tiling;
+use crate::minimap::MinimapSettings;
+use crate::renderer::BUILTIN_VIEWS;
+
+/// Usage shown for `--help` and invalid arguments.
+pub const USAGE: &str = "Usage: hypermaze [OPTIONS]
+
+Options:
+    --map <path>          Play the given map, SVG or JSON
+    --renderer <name>     Default view: fpp, klein or poincare
+    --seed <number>       Seed for the random number generator
+    --width <pixels>      Window width
+    --height <pixels>     Window height
+    --floor <p,q>         Tile the floor with the hyperbolic tiling {p,q},
+                          e.g. 7,3, or none for a plain one. Default: 5,4
+    --ceiling             Draw a ceiling, tiled like the floor
+    --minimap <name>      Show a minimap in the corner, klein or poincare.
+                          M shows or hides it
+    --minimap-size <f>    Side of the minimap, relative to the screen height
+    --minimap-opacity <f> Opacity of the minimap, from 0 to 1
+    --minimap-zoom <f>    Magnification of the minimap
+    --minimap-world-fixed Keep the world's axes fixed on the minimap,
+                          instead of the player's heading pointing up
+    --fog-of-war          Only show walls and pickups on the maps once
+                          they have been in sight
+    --edit <path>         Edit the given map, or a new one if the file
+                          does not exist
+    --convert <in> <out>  Convert a map file and exit
+    --format <name>       Format to convert to: json, legacy-json, svg or
+                          poincare-svg. Guessed from the extension by default
+    --validate <path>     Check a map for problems and exit, failing if it
+                          has any. Can be given several times
+    --help                Show this message";
+
+/// Options given on the command line.
+#[derive(Debug)]
+pub struct Options {
+    /// Path to a map to play right away, instead of showing the menu.
+    pub map: Option<String>,
+    /// Name of the view shown first.
+    pub view: String,
+    /// Tiling drawn on the floor, if any.
+    pub floor: Option<Tiling>,
+    pub ceiling: bool,
+    pub minimap: MinimapSettings,
+    /// Whether the disk views only show what the player has explored.
+    pub fog_of_war: bool,
+    /// Path to a map to open in the editor.
+    pub edit: Option<String>,
+    pub seed: Option<u64>,
+    pub width: i32,
+    pub height: i32,
+    /// Map file to convert, and where to write it.
+    pub convert: Option<(String, String)>,
+    pub format: Option<MapFormat>,
+    /// Map files to check.
+    pub validate: Vec<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            map: None,
+            view: BUILTIN_VIEWS[0].to_string(),
+            floor: Some(Tiling { p: 5, q: 4 }),
+            ceiling: false,
+            minimap: MinimapSettings::default(),
+            fog_of_war: false,
+            edit: None,
+            seed: None,
+            width: GAME_SIZE_X,
+            height: GAME_SIZE_Y,
+            convert: None,
+            format: None,
+            validate: vec![],
+        }
+    }
+}
+
+impl Options {
+    /// Reads the options of the running process.
+    /// Prints the usage and exits if they are invalid.
+    pub fn from_env() -> Options {
+        match Options::parse(std::env::args().skip(1)) {
+            Ok(options) => options,
+            Err(e) => {
+                if !e.is_empty() {
+                    eprintln!("{}\n", e);
+                }
+                eprintln!("{}", USAGE);
+                std::process::exit(if e.is_empty() { 0 } else { 1 });
+            }
+        }
+    }
+
+    /// Parses the arguments, without the program name.
+    /// Returns an empty error when help was asked for.
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+        let mut options = Options::default();
+        while let Some(arg) = args.next() {
+            if arg == "--help" || arg == "-h" {
+                return Err(String::new());
+            }
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("Missing value for {}", arg))
+            };
+            match arg.as_str() {
+                "--map" => options.map = Some(value()?),
+                "--edit" => options.edit = Some(value()?),
+                "--renderer" => {
+                    let name = value()?;
+                    if !BUILTIN_VIEWS.contains(&name.as_str()) {
+                        return Err(format!("Unknown renderer {}", name));
+                    }
+                    options.view = name;
+                }
+                "--floor" => {
+                    options.floor = match value()?.as_str() {
+                        "none" => None,
+                        tiling => Some(Tiling::parse(tiling)?),
+                    }
+                }
+                "--ceiling" => options.ceiling = true,
+                "--minimap" => {
+                    let name = value()?;
+                    if !["klein", "poincare"].contains(&name.as_str()) {
+                        return Err(format!("Unknown minimap {}", name));
+                    }
+                    options.minimap.view = name;
+                    options.minimap.shown = true;
+                }
+                "--minimap-size" => {
+                    options.minimap.size = parse_number(&arg, &value()?)?;
+                    if !(options.minimap.size > 0. && options.minimap.size <= 1.) {
+                        return Err(format!("Invalid value {} for {}", options.minimap.size, arg));
+                    }
+                }
+                "--minimap-opacity" => {
+                    options.minimap.opacity = parse_number(&arg, &value()?)?;
+                    if !(0. ..=1.).contains(&options.minimap.opacity) {
+                        return Err(format!("Invalid value {} for {}", options.minimap.opacity, arg));
+                    }
+                }
+                "--minimap-zoom" => {
+                    options.minimap.zoom = parse_number(&arg, &value()?)?;
+                    if !(options.minimap.zoom > 0. && options.minimap.zoom.is_finite()) {
+                        return Err(format!("Invalid value {} for {}", options.minimap.zoom, arg));
+                    }
+                }
+                "--minimap-world-fixed" => options.minimap.player_up = false,
+                "--fog-of-war" => options.fog_of_war = true,
+                "--seed" => options.seed = Some(parse_number(&arg, &value()?)?),
+                "--width" => options.width = parse_size(&arg, &value()?)?,
+                "--height" => options.height = parse_size(&arg, &value()?)?,
+                "--convert" => options.convert = Some((value()?, value()?)),
+                "--format" => {
+                    let name = value()?;
+                    options.format = Some(
+                        MapFormat::parse(&name)
+                            .ok_or_else(|| format!("Unknown format {}", name))?,
+                    );
+                }
+                "--validate" => options.validate.push(value()?),
+                _ => return Err(format!("Unknown option {}", arg)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Parses the value of a numeric option.
+fn parse_number<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value {} for {}", value, option))
+}
+
+/// Parses the value of a window size option, in pixels.
+fn parse_size(option: &str, value: &str) -> Result<i32, String> {
+    match parse_number(option, value)? {
+        size if size > 0 => Ok(size),
+        _ => Err(format!("Invalid value {} for {}", value, option)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn test_parse() {
+        let options = parse(&["--map", "maps/a.svg", "--renderer", "klein", "--width", "640"]).unwrap();
+        assert_eq!(options.map.as_deref(), Some("maps/a.svg"));
+        assert_eq!(options.view, "klein");
+        assert_eq!(options.width, 640);
+        assert_eq!(options.height, GAME_SIZE_Y);
+
+        assert_eq!(options.floor, Some(Tiling { p: 5, q: 4 }));
+        assert!(!options.ceiling);
+
+        let options = parse(&["--floor", "7,3", "--ceiling"]).unwrap();
+        assert_eq!(options.floor, Some(Tiling { p: 7, q: 3 }));
+        assert!(options.ceiling);
+        assert_eq!(parse(&["--floor", "none"]).unwrap().floor, None);
+        assert!(parse(&["--floor", "4,4"]).is_err());
+
+        let options = parse(&[
+            "--minimap",
+            "poincare",
+            "--minimap-size",
+            "0.25",
+            "--minimap-world-fixed",
+            "--fog-of-war",
+        ])
+        .unwrap();
+        assert_eq!(options.minimap.view, "poincare");
+        assert_eq!(options.minimap.size, 0.25);
+        assert!(options.minimap.shown && !options.minimap.player_up);
+        assert!(options.fog_of_war);
+        assert!(parse(&["--minimap", "fpp"]).is_err());
+        assert!(parse(&["--minimap-opacity", "2"]).is_err());
+        assert!(parse(&["--minimap-zoom", "0"]).is_err());
+
+        assert!(parse(&["--renderer", "raycaster"]).is_err());
+        assert!(parse(&["--seed", "abc"]).is_err());
+        assert!(parse(&["--width", "0"]).is_err());
+        assert!(parse(&["--height", "-480"]).is_err());
+        assert!(parse(&["--map"]).is_err());
+        assert!(parse(&["--fullscreen"]).is_err());
+
+        let options = parse(&["--convert", "a.svg", "b.json", "--format", "legacy-json"]).unwrap();
+        assert_eq!(options.convert, Some(("a.svg".to_string(), "b.json".to_string())));
+        assert_eq!(options.format, Some(MapFormat::LegacyJson));
+
+        let options = parse(&["--validate", "a.svg", "--validate", "b.json"]).unwrap();
+        assert_eq!(options.validate, vec!["a.svg", "b.json"]);
+        assert!(parse(&["--convert", "a.svg"]).is_err());
+        assert!(parse(&["--format", "png"]).is_err());
+    }
+}
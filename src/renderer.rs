@@ -0,0 +1,206 @@
+use macroquad::prelude::{draw_triangle, Color, KeyCode, RenderTarget, Vec2};
+use nalgebra::Matrix3;
+
+use crate::constants::*;
+use crate::game::Game;
+use crate::utils::hyperpoint::HyperPoint;
+
+/// Names of the views every build has, as given to `--renderer`.
+pub const BUILTIN_VIEWS: [&str; 3] = ["fpp", "klein", "poincare"];
+
+/// How a view is drawn, besides what is in the game.
+#[derive(Clone, Debug)]
+pub struct ViewSettings {
+    /// Magnification of the disk views, 1 showing the whole disk.
+    pub zoom: f32,
+    /// Angle the disk views are turned by, in radians.
+    pub rotation: f32,
+    /// Texture the disk views are drawn into, instead of the screen.
+    pub target: Option<RenderTarget>,
+    /// Isometry applied to the view before the disk views draw it,
+    /// to look at other parts of the map than where the player is.
+    pub pan: Matrix3<f64>,
+    /// Whether the disk views only show what the player has explored.
+    pub fog_of_war: bool,
+}
+
+impl Default for ViewSettings {
+    fn default() -> Self {
+        ViewSettings {
+            zoom: 1.,
+            rotation: 0.,
+            target: None,
+            pan: Matrix3::identity(),
+            fog_of_war: false,
+        }
+    }
+}
+
+impl ViewSettings {
+    /// Where a point of the view is drawn, once panned.
+    pub fn panned(&self, point: &HyperPoint) -> HyperPoint {
+        let mut point = *point;
+        point.transform(&self.pan);
+        point
+    }
+
+    /// Pans the view so that what is drawn at `from` is drawn at `to`.
+    /// This is a hyperbolic translation, so the map keeps its shape.
+    pub fn drag(&mut self, from: &HyperPoint, to: &HyperPoint) {
+        let translation =
+            HyperPoint::inverse_isometry(&to.centering_isometry()) * from.centering_isometry();
+        self.pan = translation * self.pan;
+    }
+
+    /// Zooms in by `steps` of `ZOOM_STEP`, or out if negative.
+    pub fn zoom_by(&mut self, steps: f32) {
+        self.zoom = (self.zoom * ZOOM_STEP.powf(steps)).clamp(MIN_ZOOM, MAX_ZOOM);
+    }
+
+    /// Puts the player back at the center, at the default scale.
+    pub fn recentre(&mut self) {
+        self.zoom = 1.;
+        self.pan = Matrix3::identity();
+    }
+}
+
+/// Something which draws the game, filling the screen or its target.
+pub trait Renderer {
+    fn render(&self, game: &Game, settings: &ViewSettings);
+
+    /// Point of the plane drawn at a point of the disk, in views which
+    /// draw the plane in one. It is in the panned view.
+    fn disk_point(&self, _x: f64, _y: f64) -> Option<HyperPoint> {
+        None
+    }
+
+    /// What went wrong while rendering since the last call,
+    /// such as textures which could not be loaded.
+    fn take_errors(&self) -> Vec<String> {
+        vec![]
+    }
+}
+
+/// Fills a fan of points of a disk view, the first one being its center.
+pub fn draw_fan(points: &[Vec2], color: Color) {
+    if let Some((&center, outline)) = points.split_first() {
+        for (i, &point) in outline.iter().enumerate() {
+            draw_triangle(center, point, outline[(i + 1) % outline.len()], color);
+        }
+    }
+}
+
+/// Views by name, in the order they are cycled through.
+pub struct ViewRegistry {
+    views: Vec<(String, Box<dyn Renderer>)>,
+}
+
+impl ViewRegistry {
+    pub fn new() -> ViewRegistry {
+        ViewRegistry { views: vec![] }
+    }
+
+    /// Adds a view, or replaces the one with the same name.
+    pub fn register(&mut self, name: &str, renderer: Box<dyn Renderer>) {
+        match self.index_of(name) {
+            Some(i) => self.views[i].1 = renderer,
+            None => self.views.push((name.to_string(), renderer)),
+        }
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.views.iter().position(|(view, _)| view == name)
+    }
+
+    pub fn get(&self, index: usize) -> &dyn Renderer {
+        self.views[index].1.as_ref()
+    }
+
+    pub fn count(&self) -> usize {
+        self.views.len()
+    }
+}
+
+/// What a key does to the view.
+#[derive(Clone, Copy, Debug)]
+pub enum ViewBinding {
+    /// Switches to the named view, or back to the previous one if it is shown.
+    Toggle(KeyCode, &'static str),
+    /// Switches to the next view of the registry.
+    Cycle(KeyCode),
+}
+
+/// Which view of the registry is shown.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ViewSelection {
+    pub current: usize,
+    /// View shown before the current one was toggled on.
+    previous: Option<usize>,
+}
+
+impl ViewSelection {
+    pub fn new(current: usize) -> ViewSelection {
+        ViewSelection {
+            current,
+            previous: None,
+        }
+    }
+
+    pub fn toggle(&mut self, view: usize) {
+        if self.current == view {
+            if let Some(previous) = self.previous.take() {
+                self.current = previous;
+            }
+        } else {
+            self.previous = Some(self.current);
+            self.current = view;
+        }
+    }
+
+    pub fn cycle(&mut self, count: usize) {
+        self.current = (self.current + 1) % count.max(1);
+        self.previous = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selection() {
+        let mut selection = ViewSelection::new(0);
+        selection.toggle(1);
+        assert_eq!(selection.current, 1);
+        selection.toggle(2);
+        assert_eq!(selection.current, 2);
+        // Toggling back returns to the view toggled from.
+        selection.toggle(2);
+        assert_eq!(selection.current, 1);
+        selection.toggle(1);
+        assert_eq!(selection.current, 1);
+
+        selection.cycle(3);
+        assert_eq!(selection.current, 2);
+        selection.cycle(3);
+        assert_eq!(selection.current, 0);
+        selection.toggle(0);
+        assert_eq!(selection.current, 0);
+    }
+
+    #[test]
+    fn test_drag() {
+        let mut settings = ViewSettings::default();
+        let (from, to) = (HyperPoint::new(0.2, 0.1), HyperPoint::new(-0.5, 0.4));
+        settings.drag(&from, &to);
+        assert!((settings.panned(&from).0 - to.0).norm() < 1e-9);
+        settings.drag(&to, &HyperPoint::new(1., -2.));
+        assert!((settings.panned(&from).0 - HyperPoint::new(1., -2.).0).norm() < 1e-9);
+
+        settings.zoom_by(1000.);
+        assert_eq!(settings.zoom, MAX_ZOOM);
+        settings.recentre();
+        assert_eq!(settings.zoom, 1.);
+        assert_eq!(settings.panned(&from).0, from.0);
+    }
+}
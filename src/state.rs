@@ -0,0 +1,725 @@
+use std::cell::RefCell;
+
+use macroquad::prelude::*;
+use macroquad::ui::*;
+
+use crate::constants::*;
+use crate::edit_mode::EditMode;
+use crate::fpp_renderer::*;
+use crate::game::campaign::*;
+use crate::game::editor::Editor;
+use crate::game::maperror::MapLoadWarning;
+use crate::game::maps::MapWatcher;
+use crate::game::save::SaveState;
+use crate::game::svgloader::Model;
+use crate::game::*;
+use crate::minimap::Minimap;
+use crate::utils::hyperpoint::HyperPoint;
+use crate::options::Options;
+use crate::poincare_renderer::*;
+use crate::renderer::*;
+use crate::top_down_renderer::*;
+
+/// All views, shared by the states, and which one is shown while playing.
+pub struct Renderers {
+    pub views: ViewRegistry,
+    selection: RefCell<ViewSelection>,
+    /// How the disk views are panned and zoomed.
+    settings: RefCell<ViewSettings>,
+    /// Point of the plane under the mouse while it drags the view.
+    dragging: RefCell<Option<HyperPoint>>,
+    minimap: Minimap,
+}
+
+impl Renderers {
+    /// Initializes the renderers. This takes a bit of time
+    /// because it needs to load the textures.
+    pub fn new(options: &Options) -> Renderers {
+        let mut views = ViewRegistry::new();
+        views.register("fpp", Box::new(FppRenderer::new(options.floor, options.ceiling)));
+        views.register("klein", Box::new(TopDownRenderer::new()));
+        views.register("poincare", Box::new(PoincareRenderer::new()));
+        let selection = ViewSelection::new(views.index_of(&options.view).unwrap_or(0));
+        Renderers {
+            views,
+            selection: RefCell::new(selection),
+            settings: RefCell::new(ViewSettings {
+                fog_of_war: options.fog_of_war,
+                ..ViewSettings::default()
+            }),
+            dragging: RefCell::new(None),
+            minimap: Minimap::new(options.minimap.clone()),
+        }
+    }
+
+    /// Switches views with the `VIEW_BINDINGS` pressed this frame,
+    /// and shows or hides the minimap. The mouse pans and zooms the
+    /// minimap while over it, and the current view elsewhere.
+    pub fn handle_keys(&self, game: &Game) {
+        self.minimap.handle_keys();
+        let mut selection = self.selection.borrow_mut();
+        for binding in &VIEW_BINDINGS {
+            match *binding {
+                ViewBinding::Toggle(key, name) if is_key_pressed(key) => {
+                    if let Some(view) = self.views.index_of(name) {
+                        selection.toggle(view);
+                    }
+                }
+                ViewBinding::Cycle(key) if is_key_pressed(key) => selection.cycle(self.views.count()),
+                _ => {}
+            }
+        }
+
+        let mut settings = self.settings.borrow_mut();
+        let mut dragging = self.dragging.borrow_mut();
+        let mut minimap = self.minimap.view.borrow_mut();
+        let mut minimap_dragging = self.minimap.dragging.borrow_mut();
+        if is_key_pressed(KEY_RECENTRE) {
+            settings.recentre();
+            minimap.recentre();
+        }
+        let over_minimap = self.minimap.view_over(&self.views, selection.current).and_then(|view| {
+            let drawn = self.minimap.settings(game, settings.fog_of_war);
+            self.minimap.mouse(&drawn).map(|mouse| (view, mouse))
+        });
+        // A drag carries on in the view it started in.
+        let on_minimap = dragging.is_none() && (minimap_dragging.is_some() || over_minimap.is_some());
+        if !on_minimap {
+            let (x, y) = mouse_position();
+            let mouse = disk_camera(&settings).screen_to_world(vec2(x, y));
+            pan_and_zoom(self.views.get(selection.current), &mut settings, &mut dragging, mouse);
+        } else if let Some((view, mouse)) = over_minimap {
+            pan_and_zoom(self.views.get(view), &mut minimap, &mut minimap_dragging, mouse);
+        } else if !is_mouse_button_down(MouseButton::Left) {
+            *minimap_dragging = None;
+        }
+    }
+
+    /// What went wrong in any view since the last call.
+    pub fn take_errors(&self) -> Vec<String> {
+        (0..self.views.count()).flat_map(|view| self.views.get(view).take_errors()).collect()
+    }
+
+    /// Whether the disk views only show what the player has explored.
+    pub fn fog_of_war(&self) -> bool {
+        self.settings.borrow().fog_of_war
+    }
+
+    /// Draws the game in the current view, with the minimap over it.
+    pub fn render(&self, game: &Game) {
+        let current = self.selection.borrow().current;
+        let settings = self.settings.borrow();
+        self.views.get(current).render(game, &settings);
+        self.minimap.draw(&self.views, current, game, settings.fog_of_war);
+    }
+}
+
+/// A screen the game can be in.
+pub enum State {
+    /// Title menu.
+    Menu { selected: usize },
+    /// List of campaign levels to start playing on.
+    MapSelect {
+        selected: usize,
+        campaign: &'static Campaign,
+        progress: Progress,
+    },
+    /// The game itself. `announced` is set once the win or failure screen
+    /// has been shown.
+    /// Maps loaded from disk are watched and reloaded when saved,
+    /// `reload_error` holds the reason the last reload failed.
+    /// `notice` tells how the last quicksave, quickload or export went.
+    Playing {
+        game: Box<Game>,
+        announced: bool,
+        watcher: Option<MapWatcher>,
+        reload_error: Option<String>,
+        notice: Option<String>,
+    },
+    /// Pause menu, shown over `Playing`.
+    Paused { selected: usize, map_name: String },
+    /// Win screen with the run's stats, shown over `Playing`.
+    Won {
+        selected: usize,
+        map_name: String,
+        /// Map of the level unlocked by this win.
+        next: Option<String>,
+        time: f64,
+        best: Option<f64>,
+        /// Whether the run was strictly faster than the best one before it.
+        new_best: bool,
+        par: Option<f64>,
+    },
+    /// Shown over `Playing` when the run failed, e.g. the time ran out.
+    Failed { selected: usize, map_name: String },
+    /// Problems found in a map, confirmed before `then` is played on it.
+    MapProblems {
+        map_name: String,
+        problems: Vec<String>,
+        then: Box<State>,
+    },
+    /// Map editor.
+    Editing(Box<EditMode>),
+    /// Shown when a map or a save could not be loaded.
+    LoadError(String),
+}
+
+/// Change of the state stack requested by the state on top.
+pub enum Transition {
+    None,
+    Push(State),
+    /// Pops the given number of states.
+    Pop(usize),
+    /// Pops the given number of states, then pushes a new one.
+    Replace(usize, State),
+    /// Like `Replace`, without autosaving the games popped.
+    Discard(usize, State),
+    Quit,
+}
+
+impl State {
+    pub fn menu() -> State {
+        State::Menu { selected: 0 }
+    }
+
+    /// Starts a new game on the given map, or explains why it can't be loaded.
+    /// Elements skipped while loading it are listed over the game,
+    /// problems the validator finds in it have to be confirmed first.
+    pub fn start(map_name: &str) -> State {
+        match maps::load_with_warnings(map_name) {
+            Ok((map, warnings)) => {
+                let problems: Vec<String> =
+                    validator::validate(&map, &FppRenderer::texture_names_for(map_name))
+                        .iter()
+                        .map(|problem| problem.to_string())
+                        .collect();
+                let mut state =
+                    State::playing(Game::new(map, map_name, Campaign::builtin().rule_for(map_name)));
+                if let State::Playing { notice, .. } = &mut state {
+                    *notice = skipped(&warnings);
+                }
+                if problems.is_empty() {
+                    state
+                } else {
+                    State::MapProblems {
+                        map_name: map_name.to_string(),
+                        problems,
+                        then: Box::new(state),
+                    }
+                }
+            }
+            Err(e) => State::LoadError(e.to_string()),
+        }
+    }
+
+    /// Opens the map editor on the given map, or a new one if there is no file.
+    pub fn edit(map_name: &str) -> State {
+        match Editor::open(map_name) {
+            Ok(editor) => State::Editing(Box::new(EditMode::new(editor))),
+            Err(e) => State::LoadError(e.to_string()),
+        }
+    }
+
+    /// Resumes the autosaved game.
+    fn resume() -> State {
+        match Game::load(AUTOSAVE_SLOT) {
+            Ok(game) => State::playing(game),
+            Err(e) => State::LoadError(e),
+        }
+    }
+
+    fn playing(game: Game) -> State {
+        let watcher = if maps::is_builtin(&game.map_name) {
+            None
+        } else {
+            Some(MapWatcher::new(&game.map_name))
+        };
+        State::Playing {
+            announced: game.is_finished(),
+            game: Box::new(game),
+            watcher,
+            reload_error: None,
+            notice: None,
+        }
+    }
+
+    /// Whether the state is drawn over the one below it.
+    fn is_overlay(&self) -> bool {
+        matches!(self, State::Paused { .. } | State::Won { .. } | State::Failed { .. })
+    }
+
+    /// Draws the state's scene.
+    fn draw(&self, renderers: &Renderers) {
+        match self {
+            State::Playing {
+                game,
+                reload_error,
+                notice,
+                ..
+            } => {
+                renderers.handle_keys(game);
+                renderers.render(game);
+                game.display_hud();
+                if let Some(e) = reload_error {
+                    root_ui().label(None, "Could not reload the map:");
+                    root_ui().label(None, e);
+                }
+                if let Some(notice) = notice {
+                    root_ui().label(None, notice);
+                }
+            }
+            State::Editing(mode) => mode.draw(renderers),
+            State::Paused { .. } | State::Won { .. } | State::Failed { .. } => {
+                set_default_camera();
+                draw_rectangle(
+                    0.,
+                    0.,
+                    screen_width(),
+                    screen_height(),
+                    Color::new(0., 0., 0., 0.6),
+                );
+            }
+            _ => {
+                set_default_camera();
+                clear_background(BLACK);
+            }
+        }
+    }
+
+    /// Handles input and advances the state by one frame.
+    fn update(&mut self, renderers: &Renderers) -> Transition {
+        match self {
+            State::Menu { selected } => {
+                let can_continue = SaveState::exists(AUTOSAVE_SLOT);
+                let mut options = vec![];
+                if can_continue {
+                    options.push("Continue");
+                }
+                options.extend(&["New game", "Select map", "Quit"]);
+
+                if is_key_pressed(KEY_EXIT) {
+                    return Transition::Quit;
+                }
+                match menu("HyperMaze", &options, selected) {
+                    Some(i) => match options[i] {
+                        "Continue" => Transition::Push(State::resume()),
+                        "New game" => match Campaign::builtin().levels.first() {
+                            Some(level) => Transition::Push(State::start(&level.map)),
+                            None => Transition::None,
+                        },
+                        "Select map" => Transition::Push(State::MapSelect {
+                            selected: 0,
+                            campaign: Campaign::builtin(),
+                            progress: Progress::load(),
+                        }),
+                        _ => Transition::Quit,
+                    },
+                    None => Transition::None,
+                }
+            }
+            State::MapSelect {
+                selected,
+                campaign,
+                progress,
+            } => {
+                let titles: Vec<String> = campaign
+                    .levels
+                    .iter()
+                    .enumerate()
+                    .map(|(i, level)| {
+                        if !progress.is_unlocked(campaign, i) {
+                            format!("{} (locked)", level.title)
+                        } else if progress.is_completed(&level.map) {
+                            format!("{} (done)", level.title)
+                        } else {
+                            level.title.clone()
+                        }
+                    })
+                    .collect();
+                let titles: Vec<&str> = titles.iter().map(|t| t.as_str()).collect();
+
+                if is_key_pressed(KEY_EXIT) {
+                    return Transition::Pop(1);
+                }
+                match menu("Select level", &titles, selected) {
+                    Some(i) if progress.is_unlocked(campaign, i) => {
+                        Transition::Replace(1, State::start(&campaign.levels[i].map))
+                    }
+                    _ => Transition::None,
+                }
+            }
+            State::Playing {
+                game,
+                announced,
+                watcher,
+                reload_error,
+                notice,
+            } => {
+                if let Some(watcher) = watcher {
+                    if watcher.poll(get_frame_time() as f64) {
+                        match watcher.load() {
+                            Ok((map, warnings)) => {
+                                game.reload(map);
+                                *reload_error = None;
+                                *notice = skipped(&warnings);
+                            }
+                            Err(e) => *reload_error = Some(e.to_string()),
+                        }
+                    }
+                }
+                if is_key_pressed(KEY_EXIT) {
+                    return Transition::Push(State::Paused {
+                        selected: 0,
+                        map_name: game.map_name.clone(),
+                    });
+                }
+                let errors = renderers.take_errors();
+                if !errors.is_empty() {
+                    *notice = Some(errors.join("; "));
+                }
+                game.fog_of_war = renderers.fog_of_war();
+                if let Some(message) = play(game) {
+                    *notice = Some(message);
+                }
+                if !game.is_finished() {
+                    // A quickload may have taken us back before the end.
+                    *announced = false;
+                } else if !*announced && game.is_failed() {
+                    *announced = true;
+                    return Transition::Push(State::Failed {
+                        selected: 0,
+                        map_name: game.map_name.clone(),
+                    });
+                } else if !*announced {
+                    *announced = true;
+
+                    let mut progress = Progress::load();
+                    progress.complete(&game.map_name);
+                    if let Err(e) = progress.save() {
+                        *notice = Some(format!("Could not save the progress: {}", e));
+                    }
+
+                    return Transition::Push(State::Won {
+                        selected: 0,
+                        map_name: game.map_name.clone(),
+                        next: Campaign::builtin()
+                            .next(&game.map_name)
+                            .map(|level| level.map.clone()),
+                        time: game.elapsed,
+                        best: game.best_time(),
+                        new_best: game.is_new_best(),
+                        par: game.par_time(),
+                    });
+                }
+                Transition::None
+            }
+            State::Paused { selected, map_name } => {
+                if is_key_pressed(KEY_EXIT) {
+                    return Transition::Pop(1);
+                }
+                let options = ["Resume", "Restart", "Edit map", "Main menu", "Quit"];
+                match menu("Paused", &options, selected) {
+                    Some(0) => Transition::Pop(1),
+                    // The run being restarted is abandoned, not saved.
+                    Some(1) => Transition::Discard(2, State::start(map_name)),
+                    Some(2) => Transition::Push(State::edit(map_name)),
+                    Some(3) => Transition::Pop(2),
+                    Some(_) => Transition::Quit,
+                    None => Transition::None,
+                }
+            }
+            State::Won {
+                selected,
+                map_name,
+                next,
+                time,
+                best,
+                new_best,
+                par,
+            } => {
+                root_ui().label(None, &format!("Finished in {:.1}s", time));
+                match best {
+                    Some(best) if !*new_best => root_ui().label(None, &format!("Best: {:.1}s", best)),
+                    _ => root_ui().label(None, "New best time!"),
+                }
+                match par {
+                    Some(par) if *time <= *par => root_ui().label(None, "Under par!"),
+                    Some(par) => root_ui().label(None, &format!("Par: {:.1}s", par)),
+                    None => {}
+                }
+
+                let mut options = vec![];
+                if next.is_some() {
+                    options.push("Next level");
+                }
+                options.extend(&["Restart", "Main menu"]);
+                match menu("You won!", &options, selected) {
+                    Some(i) => match options[i] {
+                        "Next level" => {
+                            Transition::Replace(2, State::start(next.as_ref().unwrap()))
+                        }
+                        "Restart" => Transition::Replace(2, State::start(map_name)),
+                        _ => Transition::Pop(2),
+                    },
+                    None => Transition::None,
+                }
+            }
+            State::Failed { selected, map_name } => {
+                root_ui().label(None, "Time's up!");
+                match menu("You failed", &["Restart", "Main menu"], selected) {
+                    Some(0) => Transition::Replace(2, State::start(map_name)),
+                    Some(_) => Transition::Pop(2),
+                    None => Transition::None,
+                }
+            }
+            State::Editing(mode) => {
+                if mode.update(renderers.fog_of_war()) {
+                    Transition::None
+                } else {
+                    Transition::Pop(1)
+                }
+            }
+            State::MapProblems {
+                map_name,
+                problems,
+                then,
+            } => {
+                root_ui().label(None, &format!("Problems found in {}:", map_name));
+                for problem in problems.iter() {
+                    root_ui().label(None, problem);
+                }
+                root_ui().label(None, "Press Enter to play anyway, Escape to go back");
+                if is_key_pressed(KEY_SELECT) {
+                    Transition::Replace(1, std::mem::replace(&mut **then, State::menu()))
+                } else if is_key_pressed(KEY_EXIT) {
+                    Transition::Pop(1)
+                } else {
+                    Transition::None
+                }
+            }
+            State::LoadError(message) => {
+                root_ui().label(None, "Could not load the map");
+                root_ui().label(None, message);
+                root_ui().label(None, "Press Enter to go back");
+                if is_key_pressed(KEY_SELECT) || is_key_pressed(KEY_EXIT) {
+                    Transition::Pop(1)
+                } else {
+                    Transition::None
+                }
+            }
+        }
+    }
+
+    /// Called when the state is popped off the stack.
+    /// An unfinished game is autosaved, so it can be continued later.
+    fn exit(&self) -> Result<(), String> {
+        match self {
+            State::Playing { game, .. } if game.is_finished() => SaveState::delete(AUTOSAVE_SLOT),
+            State::Playing { game, .. } => game.save(AUTOSAVE_SLOT),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Stack of states. Only the top one handles input,
+/// overlays are drawn over the states below them.
+pub struct StateStack {
+    states: Vec<State>,
+    /// Why the last game popped could not be autosaved, shown until
+    /// another state is entered.
+    autosave_error: Option<String>,
+}
+
+impl StateStack {
+    pub fn new(initial: State) -> StateStack {
+        StateStack {
+            states: vec![initial],
+            autosave_error: None,
+        }
+    }
+
+    pub fn push(&mut self, state: State) {
+        self.states.push(state);
+    }
+
+    /// Draws and updates the states for one frame.
+    /// Returns false once the stack is empty and the game should quit.
+    pub fn update(&mut self, renderers: &Renderers) -> bool {
+        let first_drawn = self
+            .states
+            .iter()
+            .rposition(|s| !s.is_overlay())
+            .unwrap_or(0);
+        for state in &self.states[first_drawn..] {
+            state.draw(renderers);
+        }
+        if let Some(e) = &self.autosave_error {
+            root_ui().label(None, &format!("Could not autosave the game: {}", e));
+        }
+
+        let transition = match self.states.last_mut() {
+            Some(state) => state.update(renderers),
+            None => return false,
+        };
+        match transition {
+            Transition::None => {}
+            Transition::Push(state) => {
+                self.autosave_error = None;
+                self.states.push(state);
+            }
+            Transition::Pop(n) => self.pop(n),
+            Transition::Replace(n, state) => {
+                self.autosave_error = None;
+                self.pop(n);
+                self.states.push(state);
+            }
+            Transition::Discard(n, state) => {
+                self.autosave_error = None;
+                let len = self.states.len();
+                self.states.truncate(len.saturating_sub(n));
+                self.states.push(state);
+            }
+            Transition::Quit => self.pop(self.states.len()),
+        }
+        !self.states.is_empty()
+    }
+
+    fn pop(&mut self, n: usize) {
+        for _ in 0..n {
+            if let Some(state) = self.states.pop() {
+                if let Err(e) = state.exit() {
+                    self.autosave_error = Some(e);
+                }
+            }
+        }
+    }
+}
+
+/// Pans a disk view by dragging it with the mouse, at `mouse` on the
+/// view's disk, and zooms it with the wheel. Views which draw no disk,
+/// like the first person one, are left alone.
+fn pan_and_zoom(
+    view: &dyn Renderer,
+    settings: &mut ViewSettings,
+    dragging: &mut Option<HyperPoint>,
+    mouse: Vec2,
+) {
+    if view.disk_point(0., 0.).is_none() {
+        return;
+    }
+    let (_, wheel) = mouse_wheel();
+    if wheel != 0. {
+        settings.zoom_by(wheel.signum());
+    }
+    let point = view.disk_point(mouse.x as f64, mouse.y as f64);
+    match (is_mouse_button_down(MouseButton::Left), *dragging, point) {
+        (true, Some(from), Some(to)) => {
+            settings.drag(&from, &to);
+            *dragging = Some(to);
+        }
+        (true, None, point) => *dragging = point,
+        _ => *dragging = None,
+    }
+}
+
+/// Lists the elements skipped while loading a map, if any were.
+fn skipped(warnings: &[MapLoadWarning]) -> Option<String> {
+    if warnings.is_empty() {
+        return None;
+    }
+    let warnings: Vec<String> = warnings.iter().map(|w| w.to_string()).collect();
+    Some(format!("Skipped while loading the map: {}", warnings.join("; ")))
+}
+
+/// Draws a list of options with the selected one marked
+/// and moves the selection with the arrow keys.
+/// Returns the index of the option chosen with Enter.
+fn menu(title: &str, options: &[&str], selected: &mut usize) -> Option<usize> {
+    if is_key_pressed(KEY_MENU_UP) && *selected > 0 {
+        *selected -= 1;
+    }
+    if is_key_pressed(KEY_MENU_DOWN) && *selected + 1 < options.len() {
+        *selected += 1;
+    }
+    *selected = (*selected).min(options.len().saturating_sub(1));
+
+    root_ui().label(None, title);
+    for (i, option) in options.iter().enumerate() {
+        let marker = if i == *selected { "> " } else { "   " };
+        root_ui().label(None, &format!("{}{}", marker, option));
+    }
+
+    if is_key_pressed(KEY_SELECT) && !options.is_empty() {
+        Some(*selected)
+    } else {
+        None
+    }
+}
+
+/// Handles the quicksave, quickload and export keys, then moves the player
+/// and advances the game like `walk`. Returns how the saving, loading
+/// or exporting went.
+pub fn play(game: &mut Game) -> Option<String> {
+    let mut notice = None;
+    if is_key_pressed(KEY_QUICKSAVE) {
+        notice = Some(match game.save(QUICKSAVE_SLOT) {
+            Ok(()) => "Game saved".to_string(),
+            Err(e) => format!("Could not save the game: {}", e),
+        });
+    }
+    if is_key_pressed(KEY_QUICKLOAD) {
+        notice = Some(match Game::load(QUICKSAVE_SLOT) {
+            Ok(loaded) => {
+                *game = loaded;
+                "Game loaded".to_string()
+            }
+            Err(e) => format!("Could not load the game: {}", e),
+        });
+    }
+    for &(key, model) in &[
+        (KEY_EXPORT_KLEIN, Model::Klein),
+        (KEY_EXPORT_POINCARE, Model::Poincare),
+    ] {
+        if is_key_pressed(key) {
+            notice = Some(match svgwriter::export(&game.map, &game.map_name, model) {
+                Ok(path) => format!("Exported the map to {}", path.display()),
+                Err(e) => format!("Could not export the map: {}", e),
+            });
+        }
+    }
+
+    walk(game);
+    notice
+}
+
+/// Moves the player according to the input and advances the game by one frame.
+pub fn walk(game: &mut Game) {
+    let movement = if is_key_down(KEY_FASTER) {
+        MOVEMENT_SPEED * get_frame_time() as f64 * 2.
+    } else {
+        MOVEMENT_SPEED * get_frame_time() as f64
+    };
+    let rotation = ROTATION_SPEED * get_frame_time() as f64;
+    if is_key_down(KEY_FORWARD) || is_key_down(KEY_FORWARD_ALT) {
+        game.move_player(-movement);
+    }
+    if is_key_down(KEY_BACKWARD) || is_key_down(KEY_BACKWARD_ALT) {
+        game.move_player(movement);
+    }
+    if is_key_down(KEY_STRAFE_L) {
+        game.strafe_player(movement);
+    }
+    if is_key_down(KEY_STRAFE_R) {
+        game.strafe_player(-movement);
+    }
+    if is_key_down(KEY_LEFT) {
+        game.rotate_player(-rotation);
+    }
+    if is_key_down(KEY_RIGHT) {
+        game.rotate_player(rotation);
+    }
+    if is_key_pressed(KEY_FLASHLIGHT) {
+        game.flashlight = !game.flashlight;
+    }
+    game.tick(get_frame_time() as f64);
+}
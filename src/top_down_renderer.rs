@@ -1,7 +1,27 @@
-use crate::constants::_ASPECT_RATIO;
-use crate::{game::Game, utils::kleinpoint::*};
+use crate::constants::{EXIT_COLOR, EXPLORED_COLOR, GHOST_COLOR};
+use crate::game::exploration::Exploration;
+use crate::renderer::{draw_fan, Renderer, ViewSettings};
+use crate::utils::hyperpoint::HyperPoint;
+use crate::{game::hypermap::HyperMap, game::Game, utils::kleinpoint::*};
 use macroquad::prelude::*;
 
+/// Camera showing the unit disk, with the view's x axis pointing up
+/// unless the settings turn it.
+pub fn disk_camera(settings: &ViewSettings) -> Camera2D {
+    // Render targets are square.
+    let aspect = match settings.target {
+        Some(_) => 1.,
+        None => screen_height() / screen_width(),
+    };
+    Camera2D {
+        target: vec2(0., 0.),
+        zoom: vec2(aspect, 1.) * settings.zoom,
+        rotation: 90. + settings.rotation.to_degrees(),
+        render_target: settings.target,
+        ..Default::default()
+    }
+}
+
 /// Draws a top-down view on a Klein disk.
 pub struct TopDownRenderer {}
 
@@ -10,31 +30,60 @@ impl TopDownRenderer {
         TopDownRenderer {}
     }
 
-    pub fn render(&self, game: &Game) {
-        // set camera and outline:
+    /// Draws the walls and objects of a map, as seen from the origin of its view.
+    /// Given what the player has explored, only that is drawn.
+    /// The disk's camera is left set, for the caller to draw over the map.
+    pub fn render_map(&self, map: &HyperMap, explored: Option<&Exploration>, settings: &ViewSettings) {
+        // set camera, so its target is the one cleared, and outline:
+        set_camera(&disk_camera(settings));
         clear_background(BLACK);
-        set_camera(&Camera2D {
-            target: vec2(0., 0.),
-            zoom: vec2(1. / _ASPECT_RATIO, 1.),
-            rotation: 90.,
-            ..Default::default()
-        });
         draw_circle_lines(0., 0., 1., 0.005, WHITE);
-        draw_circle(0., 0., 0.005, WHITE);
+
+        // shade explored regions:
+        if let Some(explored) = explored {
+            for fan in explored.regions_in_view(map) {
+                let points: Vec<_> = fan
+                    .iter()
+                    .map(|point| {
+                        let point = KleinPoint::from(&settings.panned(point));
+                        vec2(point.0.x as f32, point.0.y as f32)
+                    })
+                    .collect();
+                draw_fan(&points, EXPLORED_COLOR);
+            }
+        }
+
+        // The player is at the origin of the view, facing its x axis.
+        let player = KleinPoint::from(&settings.panned(&HyperPoint::new(0., 0.)));
+        let ahead = KleinPoint::from(&settings.panned(&HyperPoint::new(0.04, 0.)));
+        let (x, y) = (player.0.x as f32, player.0.y as f32);
+        draw_circle(x, y, 0.005, WHITE);
+        draw_line(x, y, ahead.0.x as f32, ahead.0.y as f32, 0.004, WHITE);
 
         // draw walls:
-        game.map
-            .get_walls_iter()
-            .map(|w| KleinWall::from(w.clone()))
+        map.get_walls_iter()
+            .enumerate()
+            .filter(|(i, _)| explored.is_none_or(|e| e.wall_seen(*i)))
+            .map(|(_, w)| {
+                let mut wall = w.clone();
+                wall.beginning = settings.panned(&wall.beginning);
+                wall.end = settings.panned(&wall.end);
+                KleinWall::from(wall)
+            })
             .for_each(|wall| {
                 self.draw_wall_klein(&wall);
             });
 
         // draw objects:
-        game.map.get_objects_iter().for_each(|obj| {
-            let obj = KleinObject::from(obj.clone());
-            self.draw_object_klein(&obj);
-        });
+        map.get_objects_iter()
+            .enumerate()
+            .filter(|(i, _)| explored.is_none_or(|e| e.object_seen(*i)))
+            .for_each(|(_, obj)| {
+                let mut obj = obj.clone();
+                obj.position = settings.panned(&obj.position);
+                let obj = KleinObject::from(&obj);
+                self.draw_object_klein(&obj);
+            });
     }
 
     fn draw_wall_klein(&self, wall: &KleinWall) {
@@ -67,3 +116,40 @@ impl TopDownRenderer {
         }
     }
 }
+
+impl Renderer for TopDownRenderer {
+    fn render(&self, game: &Game, settings: &ViewSettings) {
+        let explored = Some(&game.exploration).filter(|_| settings.fog_of_war);
+        self.render_map(&game.map, explored, settings);
+
+        // draw exit:
+        let exit = game
+            .exit_position()
+            .filter(|_| explored.is_none_or(|e| e.exit_seen()));
+        if let Some(exit) = exit {
+            let exit = KleinPoint::from(&settings.panned(&exit));
+            draw_circle(exit.0.x as f32, exit.0.y as f32, 0.015, EXIT_COLOR);
+        }
+
+        // draw ghost of the best run:
+        if let Some(ghost) = game.ghost_position() {
+            let ghost = KleinPoint::from(&settings.panned(&ghost));
+            draw_circle_lines(
+                ghost.0.x as f32,
+                ghost.0.y as f32,
+                0.01,
+                0.003,
+                GHOST_COLOR,
+            );
+        }
+        set_default_camera();
+    }
+
+    fn disk_point(&self, x: f64, y: f64) -> Option<HyperPoint> {
+        if x * x + y * y < 1. {
+            Some(HyperPoint::from(KleinPoint::new(x, y)))
+        } else {
+            None
+        }
+    }
+}
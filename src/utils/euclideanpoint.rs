@@ -9,13 +9,6 @@ pub struct EuclideanPoint {
     pub y: f64,
 }
 
-pub struct EuclideanWall {
-    pub beginning: EuclideanPoint,
-    pub end: EuclideanPoint,
-    pub texture: String,
-    pub height: f64,
-}
-
 impl From<&HyperPoint> for EuclideanPoint {
     fn from(h: &HyperPoint) -> EuclideanPoint {
         // get polar coords on hyperboloid
@@ -44,30 +37,10 @@ impl From<&KleinPoint> for EuclideanPoint {
     }
 }
 
-impl From<&HyperWall> for EuclideanWall {
-    fn from(h: &HyperWall) -> EuclideanWall {
-        EuclideanWall {
-            beginning: EuclideanPoint::from(&h.beginning),
-            end: EuclideanPoint::from(&h.end),
-            texture: h.texture.clone(),
-            height: h.height,
-        }
-    }
-}
-
-impl From<&KleinWall> for EuclideanWall {
-    fn from(h: &KleinWall) -> EuclideanWall {
-        EuclideanWall {
-            beginning: EuclideanPoint::from(&h.beginning),
-            end: EuclideanPoint::from(&h.end),
-            texture: h.texture.clone(),
-            height: h.height,
-        }
-    }
-}
 pub struct EuclideanObject {
     pub position: EuclideanPoint,
     pub active: bool,
+    pub size: f64,
 }
 
 impl From<&HyperObject> for EuclideanObject {
@@ -75,6 +48,7 @@ impl From<&HyperObject> for EuclideanObject {
         EuclideanObject {
             position: EuclideanPoint::from(&object.position),
             active: object.active,
+            size: object.size,
         }
     }
 }
@@ -84,6 +58,7 @@ impl From<&KleinObject> for EuclideanObject {
         EuclideanObject {
             position: EuclideanPoint::from(&object.position),
             active: object.active,
+            size: object.size,
         }
     }
 }
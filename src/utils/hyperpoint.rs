@@ -1,23 +1,24 @@
 use std::cmp;
 
+use crate::constants::OBJECT_RADIUS;
 use crate::utils::*;
 use cmp::Ordering;
 use nalgebra::*;
 use poincarepoint::*;
 use kleinpoint::*;
 use point::{Point, Wall};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 /// Struct representing a point on the Minkowski
 /// hyperboloid model.
 /// Wrapper for nalgebra's Point3.
-#[derive(Clone, Debug, Deserialize, Copy)]
+#[derive(Clone, Debug, Serialize, Deserialize, Copy)]
 pub struct HyperPoint(pub Point3<f64>);
 
 impl From<PoincarePoint> for HyperPoint {
     fn from(poincare_point: PoincarePoint) -> Self {
-        //Minkowski metric
-        let norm_squared = PoincarePoint::minkowski_dot(&poincare_point, &poincare_point);
+        // Euclidean norm of the point in the disk.
+        let norm_squared = poincare_point.0.coords.norm_squared();
         HyperPoint::new_with_z(
             (poincare_point.0[0] * 2.0) / (1.0 - norm_squared),
             (poincare_point.0[1] * 2.0) / (1.0 - norm_squared),
@@ -58,20 +59,25 @@ impl HyperPoint {
         }
     }
 
-    /// Rotates the point around the z axis at origin. Ordinary rotation.
-    pub fn rotate(&mut self, angle: f64) {
-        let rot = Rotation3::from_axis_angle(
+    /// Applies an isometry of the hyperboloid, given as a matrix, to the point.
+    pub fn transform(&mut self, isometry: &Matrix3<f64>) {
+        self.0 = isometry * &self.0;
+    }
+
+    /// Matrix of the rotation around the z axis at the origin. Ordinary rotation.
+    pub fn rotation_matrix(angle: f64) -> Matrix3<f64> {
+        Rotation3::from_axis_angle(
             &Unit::new_normalize(Vector3::<f64>::new(0.0, 0.0, 1.0)),
             angle,
-        );
-        self.0 = rot.transform_point(&self.0);
+        )
+        .into_inner()
     }
 
-    /// Performs the equivalent of translation in the hyperboloid model,
+    /// Matrix of the equivalent of translation in the hyperboloid model,
     /// "rotating" around the x and y axes.
     /// See the following for the explanation:
     /// https://math.stackexchange.com/questions/1862340/what-are-the-hyperbolic-rotation-matrices-in-3-and-4-dimensions?newreg=0a895728ef9c48ad814e2f06eafb3862
-    pub fn translate(&mut self, x: f64, y: f64) {
+    pub fn translation_matrix(x: f64, y: f64) -> Matrix3<f64> {
         let coshb = f64::cosh(x);
         let sinhb = f64::sinh(x);
         let coshy = f64::cosh(-y);
@@ -79,8 +85,50 @@ impl HyperPoint {
         let translation1 = Matrix3::new(coshb, 0., sinhb, 0., 1., 0., sinhb, 0., coshb);
         let translation2 = Matrix3::new(1., 0., 0., 0., coshy, sinhy, 0., sinhy, coshy);
 
-        let translation = translation1 * translation2;
-        self.0 = translation * &self.0;
+        translation1 * translation2
+    }
+
+    /// Inverse of an isometry of the hyperboloid.
+    /// Isometries preserve the Minkowski metric J = diag(1, 1, -1),
+    /// so the inverse is simply J * M^T * J.
+    pub fn inverse_isometry(isometry: &Matrix3<f64>) -> Matrix3<f64> {
+        let j = Matrix3::from_diagonal(&Vector3::new(1., 1., -1.));
+        j * isometry.transpose() * j
+    }
+
+    /// Isometry moving the point to the origin, without rotating
+    /// the geodesic through them.
+    pub fn centering_isometry(&self) -> Matrix3<f64> {
+        // The boost moving the origin to the point, inverted.
+        let (x, y, z) = (self.0.x, self.0.y, self.0.z);
+        let boost = Matrix3::new(
+            1. + x * x / (1. + z), x * y / (1. + z), x,
+            x * y / (1. + z), 1. + y * y / (1. + z), y,
+            x, y, z,
+        );
+        HyperPoint::inverse_isometry(&boost)
+    }
+
+    /// Point at the given distance from this one, along the geodesic to `to`.
+    pub fn towards(&self, to: &HyperPoint, distance: f64) -> HyperPoint {
+        let length = self.distance_to(to);
+        if length <= 1e-9 || length.is_nan() {
+            return *self;
+        }
+        let a = (length - distance).sinh() / length.sinh();
+        let b = distance.sinh() / length.sinh();
+        HyperPoint::new_with_z(
+            a * self.0.x + b * to.0.x,
+            a * self.0.y + b * to.0.y,
+            a * self.0.z + b * to.0.z,
+        )
+    }
+
+    /// Pulls a point which drifted off the hyperboloid,
+    /// e.g. after interpolation, back onto it.
+    pub fn normalize(&mut self) {
+        let norm = (-HyperPoint::minkowski_dot(self, self)).sqrt();
+        self.0.coords /= norm;
     }
 }
 
@@ -93,7 +141,8 @@ impl point::Point for HyperPoint {
 
     /// Distance to origin in the Minkowski hyperboloid metric.
     fn distance_to_origin(&self) -> f64 {
-        let minkowski_bilinear: f64 = self.0[2];
+        // Rounding can put points near the origin slightly below it.
+        let minkowski_bilinear: f64 = self.0[2].max(1.);
         minkowski_bilinear.acosh()
     }
 
@@ -114,7 +163,7 @@ impl point::Point for HyperPoint {
     }
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct HyperWall {
     pub beginning: HyperPoint,
     pub end: HyperPoint,
@@ -143,12 +192,21 @@ impl HyperWall {
 }
 
 impl Wall for HyperWall {
-    /// Distance to the closest end of the wall.
+    /// Distance from the origin to the closest point of the wall.
+    /// Geodesics are straight in the Klein disk, where points closer
+    /// to the center are closer to the origin, so it is found there.
     fn distance_to_closest_point(&self) -> f64 {
-        let dist_a = self.beginning.distance_to_origin();
-        let dist_b = self.end.distance_to_origin();
-
-        dist_a.min(dist_b)
+        let klein = |p: &HyperPoint| (p.0.x / p.0.z, p.0.y / p.0.z);
+        let (a, b) = (klein(&self.beginning), klein(&self.end));
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let length = dx * dx + dy * dy;
+        let t = if length > 0. {
+            (-(a.0 * dx + a.1 * dy) / length).clamp(0., 1.)
+        } else {
+            0.
+        };
+        let (x, y) = (a.0 + t * dx, a.1 + t * dy);
+        (x * x + y * y).sqrt().min(1.).atanh()
     }
 
     fn intersection(&self, _angle: f64) -> Option<f64> {
@@ -200,10 +258,18 @@ impl PartialOrd for HyperWall {
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct HyperObject {
     pub position: HyperPoint,
     pub active: bool,
+    /// Radius of the object.
+    #[serde(default = "default_object_size")]
+    pub size: f64,
+}
+
+/// Radius of objects which don't set one.
+pub fn default_object_size() -> f64 {
+    OBJECT_RADIUS as f64
 }
 
 impl From<PoincareObject> for HyperObject {
@@ -211,6 +277,7 @@ impl From<PoincareObject> for HyperObject {
         HyperObject {
             position: poincare_object.position.into(),
             active: poincare_object.active,
+            size: poincare_object.size,
         }
     }
 }
@@ -220,6 +287,58 @@ impl From<KleinObject> for HyperObject {
         HyperObject {
             position: klein_object.position.into(),
             active: klein_object.active,
+            size: klein_object.size,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_poincare_round_trip() {
+        let point = HyperPoint::from(PoincarePoint::new(0.3, -0.4));
+        assert!((HyperPoint::minkowski_dot(&point, &point) + 1.).abs() < 1e-9);
+        let back = PoincarePoint::from(point);
+        assert!((back.0.x - 0.3).abs() < 1e-9);
+        assert!((back.0.y + 0.4).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_towards() {
+        let from = HyperPoint::new(0.3, -0.2);
+        let to = HyperPoint::new(-0.5, 0.7);
+        let length = from.distance_to(&to);
+        for &distance in &[0.1, 0.4, length - 0.1] {
+            let point = from.towards(&to, distance);
+            assert!((HyperPoint::minkowski_dot(&point, &point) + 1.).abs() < 1e-9);
+            assert!((from.distance_to(&point) - distance).abs() < 1e-9);
+            assert!((point.distance_to(&to) - (length - distance)).abs() < 1e-9);
         }
+        assert_eq!(from.towards(&from, 0.1).0, from.0);
+    }
+
+    #[test]
+    fn test_distance_to_closest_point() {
+        let wall = |from: (f64, f64), to: (f64, f64)| HyperWall {
+            beginning: HyperPoint::new(from.0, from.1),
+            end: HyperPoint::new(to.0, to.1),
+            texture: String::new(),
+            height: 0.1,
+        };
+        // The closest point is inside the wall, where it crosses the x axis,
+        // straight between its ends on the Klein disk.
+        let across = wall((0.5, -1.), (0.5, 1.));
+        let foot = HyperPoint::from(KleinPoint::new(1. / 3., 0.));
+        assert!((across.distance_to_closest_point() - foot.distance_to_origin()).abs() < 1e-9);
+
+        // Past either end, it is that end.
+        let (near, far) = (HyperPoint::new(0.3, 0.2), HyperPoint::new(0.6, 0.9));
+        let beginning_closest = wall((0.3, 0.2), (0.6, 0.9));
+        let end_closest = wall((0.6, 0.9), (0.3, 0.2));
+        assert!((beginning_closest.distance_to_closest_point() - near.distance_to_origin()).abs() < 1e-9);
+        assert!((end_closest.distance_to_closest_point() - near.distance_to_origin()).abs() < 1e-9);
+        assert!(beginning_closest.distance_to_closest_point() < far.distance_to_origin());
     }
 }
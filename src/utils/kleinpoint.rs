@@ -97,6 +97,8 @@ impl From<HyperWall> for KleinWall {
 pub struct KleinObject {
     pub position: KleinPoint,
     pub active: bool,
+    #[serde(default = "hyperpoint::default_object_size")]
+    pub size: f64,
 }
 
 impl From<&HyperObject> for KleinObject {
@@ -104,6 +106,7 @@ impl From<&HyperObject> for KleinObject {
         KleinObject {
             position: KleinPoint::from(&obj.position),
             active: obj.active,
+            size: obj.size,
         }
     }
 }
@@ -3,7 +3,7 @@ use std::cmp::Ordering;
 use hyperpoint::{HyperPoint, HyperWall};
 use nalgebra::*;
 use point::Point;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::utils::hyperpoint;
 
@@ -12,7 +12,7 @@ use super::{hyperpoint::HyperObject, point};
 /// Struct representing a point on the
 /// Poincare disk model.
 /// Wrapper for nalgebra's Point2.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PoincarePoint(pub Point2<f64>);
 
 impl From<HyperPoint> for PoincarePoint {
@@ -76,7 +76,7 @@ impl point::Point for PoincarePoint {
     }
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PoincareWall {
     pub beginning: PoincarePoint,
     pub end: PoincarePoint,
@@ -95,10 +95,12 @@ impl From<HyperWall> for PoincareWall {
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PoincareObject {
     pub position: PoincarePoint,
     pub active: bool,
+    #[serde(default = "hyperpoint::default_object_size")]
+    pub size: f64,
 }
 
 impl From<&HyperObject> for PoincareObject {
@@ -106,6 +108,7 @@ impl From<&HyperObject> for PoincareObject {
         PoincareObject {
             position: PoincarePoint::from(&obj.position),
             active: obj.active,
+            size: obj.size,
         }
     }
 }